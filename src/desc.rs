@@ -0,0 +1,572 @@
+
+use super::*;
+use syntax::desc as sdesc;
+
+/// Values supplied by the program that are used by `@if`,
+/// `@for` and `$name` whilst creating nodes from a description
+/// document.
+///
+/// `@if` treats missing values as `false`. Using a `$name`
+/// that isn't a template parameter, `@for` item or value in
+/// the context fails to create the nodes, as does a `@for`
+/// over a value that isn't a list.
+///
+/// # Example
+///
+/// ```
+/// # extern crate fungui;
+/// # use fungui::*;
+/// # enum MyExt {}
+/// # impl Extension for MyExt {
+/// #     type NodeData = ();
+/// #     type Value = ();
+/// #     fn new_data() {}
+/// #     fn style_properties<'a, F>(_prop: F) where F: FnMut(StaticKey) + 'a {}
+/// #     fn update_data(_: &Styles<MyExt>, _: &NodeChain<MyExt>, _: &Rule<MyExt>, _: &mut ()) -> DirtyFlags {
+/// #         DirtyFlags::empty()
+/// #     }
+/// #     fn reset_unset_data(_: &FnvHashSet<StaticKey>, _: &mut ()) -> DirtyFlags {
+/// #         DirtyFlags::empty()
+/// #     }
+/// # }
+/// # fn main() {
+/// let mut data = DataContext::new();
+/// data.set("logged_in", true);
+/// data.set_list("entries", vec!["Open".to_owned(), "Save".to_owned()]);
+/// let menu: Node<MyExt> = Node::from_str_with_data(r#"
+/// menu {
+///     @if(logged_in) { "Log out" }
+///     @for(item in entries) {
+///         entry { $item }
+///     }
+/// }
+/// "#, &data).unwrap();
+/// assert_eq!(menu.children().len(), 3);
+/// # }
+/// ```
+pub struct DataContext<E: Extension> {
+    values: FnvHashMap<String, Value<E>>,
+    lists: FnvHashMap<String, Vec<Value<E>>>,
+}
+
+impl <E> DataContext<E>
+    where E: Extension
+{
+    /// Creates an empty data context
+    pub fn new() -> DataContext<E> {
+        DataContext {
+            values: FnvHashMap::default(),
+            lists: FnvHashMap::default(),
+        }
+    }
+
+    /// Sets the named value
+    pub fn set<V>(&mut self, key: &str, v: V)
+        where V: ConvertValue<E>
+    {
+        self.values.insert(key.into(), V::to_value(v));
+    }
+
+    /// Sets the named list used by `@for`
+    pub fn set_list<V, I>(&mut self, key: &str, items: I)
+        where V: ConvertValue<E>,
+              I: IntoIterator<Item = V>
+    {
+        self.lists.insert(key.into(), items.into_iter().map(V::to_value).collect());
+    }
+}
+
+impl <E> Default for DataContext<E>
+    where E: Extension
+{
+    fn default() -> DataContext<E> {
+        DataContext::new()
+    }
+}
+
+/// The values available whilst building nodes
+struct Scope<'a, E: Extension> {
+    /// The values of the current template's parameters
+    /// and `@for` items
+    params: FnvHashMap<&'a str, Value<E>>,
+    /// The caller's children that haven't been placed
+    /// yet and the slot they are for
+    slots: Vec<(Option<String>, Node<E>)>,
+    /// The current template's parameters that weren't
+    /// given a value
+    unset: Vec<&'a str>,
+    data: Option<&'a DataContext<E>>,
+}
+
+impl <'a, E> Scope<'a, E>
+    where E: Extension
+{
+    fn new(data: Option<&'a DataContext<E>>) -> Scope<'a, E> {
+        Scope {
+            params: FnvHashMap::default(),
+            slots: Vec::new(),
+            unset: Vec::new(),
+            data,
+        }
+    }
+
+    /// Looks up a parameter falling back to the data context
+    fn param(&self, name: &str) -> Option<&Value<E>> {
+        self.params.get(name)
+            .or_else(|| self.data.and_then(|v| v.values.get(name)))
+    }
+
+    /// Looks up the parameter used by `$name`. Parameters
+    /// without a value return `None` and unknown names fail.
+    fn lookup(&self, name: &syntax::Ident) -> Result<Option<&Value<E>>, syntax::PError<'static>> {
+        if let Some(v) = self.param(&name.name) {
+            Ok(Some(v))
+        } else if self.unset.contains(&&*name.name) {
+            Ok(None)
+        } else if self.data.and_then(|v| v.lists.get(&*name.name)).is_some() {
+            Err(error(name.position, "Lists set via `set_list` can only be used by `@if` and `@for`"))
+        } else {
+            Err(error(name.position, "Unknown parameter"))
+        }
+    }
+
+    /// Returns whether the value is set and isn't `false`.
+    /// Lists pass when they aren't empty.
+    fn test(&self, name: &str) -> bool {
+        match self.param(name) {
+            Some(Value::Boolean(v)) => *v,
+            Some(Value::List(v)) => !v.is_empty(),
+            Some(_) => true,
+            None => matches!(self.data.and_then(|v| v.lists.get(name)), Some(v) if !v.is_empty()),
+        }
+    }
+}
+
+/// Creates nodes from a description document, expanding
+/// templates where they are used.
+pub(crate) struct Builder<'a> {
+    templates: FnvHashMap<&'a str, &'a sdesc::Template<'a>>,
+    // The templates currently being expanded. Used to stop
+    // a template from expanding itself forever
+    expanding: Vec<&'a str>,
+}
+
+impl <'a> Builder<'a> {
+    /// Creates a builder using the passed templates. Later
+    /// templates replace earlier ones with the same name.
+    pub(crate) fn new<I>(templates: I) -> Builder<'a>
+        where I: IntoIterator<Item = &'a sdesc::Template<'a>>
+    {
+        Builder {
+            templates: templates.into_iter()
+                .map(|v| (&*v.name.name, v))
+                .collect(),
+            expanding: Vec::new(),
+        }
+    }
+
+    /// Creates a node for each of the passed top level nodes
+    pub(crate) fn nodes<E>(&mut self, nodes: &'a [sdesc::Node<'a>], data: &'a DataContext<E>) -> Result<Vec<Node<E>>, syntax::PError<'static>>
+        where E: Extension
+    {
+        self.children(nodes, &mut Scope::new(Some(data)))
+    }
+
+    fn element<E>(&mut self, desc: &'a sdesc::Element<'a>, scope: &mut Scope<'a, E>) -> Result<Node<E>, syntax::PError<'static>>
+        where E: Extension
+    {
+        let name = &*desc.name.name;
+        if let Some(template) = self.templates.get(name).cloned() {
+            if !self.expanding.contains(&name) {
+                let properties = properties(&desc.properties, scope)?;
+                let children = self.children(&desc.nodes, scope)?
+                    .into_iter()
+                    .map(|c| {
+                        let slot = c.inner.borrow_mut().properties.remove("slot")
+                            .and_then(|v| String::from_value(v));
+                        (slot, c)
+                    })
+                    .collect();
+                return self.instantiate(template, properties, children, scope.data);
+            }
+        }
+        let node = Node {
+            inner: Rc::new(RefCell::new(NodeInner {
+                value: NodeValue::Element(Element {
+                    name: name.into(),
+                    children: Vec::with_capacity(desc.nodes.len()),
+                }),
+                properties: properties(&desc.properties, scope)?,
+                .. Default::default()
+            })),
+        };
+        for c in self.children(&desc.nodes, scope)? {
+            node.add_child(c);
+        }
+        Ok(node)
+    }
+
+    fn instantiate<E>(
+        &mut self,
+        template: &'a sdesc::Template<'a>,
+        mut properties: FnvHashMap<String, Value<E>>,
+        children: Vec<(Option<String>, Node<E>)>,
+        data: Option<&'a DataContext<E>>,
+    ) -> Result<Node<E>, syntax::PError<'static>>
+        where E: Extension
+    {
+        let mut scope = Scope {
+            slots: children,
+            .. Scope::new(data)
+        };
+        for (name, default) in &template.parameters {
+            let val = match properties.remove(&*name.name) {
+                Some(v) => Some(v),
+                None => match *default {
+                    Some(ref v) => value(v, &Scope::new(data))?,
+                    None => None,
+                },
+            };
+            if let Some(val) = val {
+                scope.params.insert(&*name.name, val);
+            } else {
+                scope.unset.push(&*name.name);
+            }
+        }
+
+        self.expanding.push(&template.name.name);
+        let node = self.element(&template.root, &mut scope);
+        self.expanding.pop();
+        let node = node?;
+
+        // Properties that aren't parameters and children without
+        // a matching slot are added to the template's root element
+        node.inner.borrow_mut().properties.extend(properties);
+        for (_, c) in scope.slots {
+            node.add_child(c);
+        }
+        Ok(node)
+    }
+
+    fn children<E>(&mut self, nodes: &'a [sdesc::Node<'a>], scope: &mut Scope<'a, E>) -> Result<Vec<Node<E>>, syntax::PError<'static>>
+        where E: Extension
+    {
+        let mut children = Vec::with_capacity(nodes.len());
+        for n in nodes {
+            children.push(match *n {
+                sdesc::Node::Element(ref e) => self.element(e, scope)?,
+                sdesc::Node::Text(ref t, _, ref props) => text(unescape(t), props, scope)?,
+                sdesc::Node::Parameter(ref name, ref props) => match scope.lookup(name)? {
+                    Some(Value::String(v)) => text(v.clone(), props, scope)?,
+                    Some(Value::Integer(v)) => text(v.to_string(), props, scope)?,
+                    Some(Value::Float(v)) => text(v.to_string(), props, scope)?,
+                    Some(Value::Boolean(v)) => text(v.to_string(), props, scope)?,
+                    Some(Value::Color(v)) => text(v.to_string(), props, scope)?,
+                    Some(Value::Atom(v)) => text(v.clone(), props, scope)?,
+                    Some(Value::List(_)) | Some(Value::ExtValue(_)) => return Err(error(
+                        name.position, "Lists and extension values can't be used as text",
+                    )),
+                    None => continue,
+                },
+                sdesc::Node::Slot(ref name, _) => {
+                    let name = name.as_ref().map(|v| &*v.name);
+                    let mut idx = 0;
+                    while idx < scope.slots.len() {
+                        if scope.slots[idx].0.as_deref() == name {
+                            children.push(scope.slots.remove(idx).1);
+                        } else {
+                            idx += 1;
+                        }
+                    }
+                    continue;
+                },
+                sdesc::Node::If(ref v) => {
+                    let nodes = if scope.test(&v.name.name) != v.negated {
+                        &v.nodes
+                    } else {
+                        &v.else_nodes
+                    };
+                    children.extend(self.children(nodes, scope)?);
+                    continue;
+                },
+                sdesc::Node::For(ref v) => {
+                    // Lists set via `set_list` or list values
+                    let items = match scope.data.and_then(|d| d.lists.get(&*v.list.name)) {
+                        Some(items) => items.clone(),
+                        None => match scope.lookup(&v.list)? {
+                            Some(Value::List(items)) => items.clone(),
+                            Some(_) => return Err(error(v.list.position, "`@for` requires a list")),
+                            None => continue,
+                        },
+                    };
+                    let prev = scope.params.remove(&*v.item.name);
+                    for item in items {
+                        scope.params.insert(&v.item.name, item);
+                        children.extend(self.children(&v.nodes, scope)?);
+                    }
+                    scope.params.remove(&*v.item.name);
+                    if let Some(prev) = prev {
+                        scope.params.insert(&v.item.name, prev);
+                    }
+                    continue;
+                },
+            });
+        }
+        Ok(children)
+    }
+}
+
+fn error(position: syntax::Position, msg: &'static str) -> syntax::PError<'static> {
+    syntax::Errors::new(
+        position.into(),
+        syntax::Error::Message(syntax::Info::Borrowed(msg)),
+    )
+}
+
+fn text<'a, E>(
+    text: String,
+    props: &'a FnvHashMap<syntax::Ident<'a>, sdesc::ValueType<'a>>,
+    scope: &Scope<'a, E>,
+) -> Result<Node<E>, syntax::PError<'static>>
+    where E: Extension
+{
+    Ok(Node {
+        inner: Rc::new(RefCell::new(NodeInner {
+            value: NodeValue::Text(text),
+            properties: properties(props, scope)?,
+            .. Default::default()
+        })),
+    })
+}
+
+fn properties<'a, E>(
+    props: &'a FnvHashMap<syntax::Ident<'a>, sdesc::ValueType<'a>>,
+    scope: &Scope<'a, E>,
+) -> Result<FnvHashMap<String, Value<E>>, syntax::PError<'static>>
+    where E: Extension
+{
+    let mut out = FnvHashMap::default();
+    for (n, v) in props {
+        if let Some(v) = value(v, scope)? {
+            out.insert(n.name.to_string(), v);
+        }
+    }
+    Ok(out)
+}
+
+/// Converts the value, replacing parameters with their
+/// value. Parameters without a value return `None`.
+fn value<'a, E>(v: &sdesc::ValueType<'a>, scope: &Scope<'a, E>) -> Result<Option<Value<E>>, syntax::PError<'static>>
+    where E: Extension
+{
+    Ok(Some(match v.value {
+        sdesc::Value::Boolean(val) => Value::Boolean(val),
+        sdesc::Value::Integer(val) => Value::Integer(val),
+        sdesc::Value::Float(val) => Value::Float(val),
+        sdesc::Value::String(ref val) => Value::String(unescape(val)),
+        sdesc::Value::Color(val) => Value::Color(val),
+        sdesc::Value::Atom(ref val) => Value::Atom(val.name.to_string()),
+        sdesc::Value::List(ref val) => {
+            let mut list = Vec::with_capacity(val.len());
+            for v in val {
+                match value(v, scope)? {
+                    Some(v) => list.push(v),
+                    None => return Ok(None),
+                }
+            }
+            Value::List(list)
+        },
+        // Already checked by `check_literals`
+        sdesc::Value::Tagged(ref tag, ref val) => match E::parse_literal(&tag.name, &unescape(val)) {
+            Ok(v) => Value::ExtValue(v),
+            Err(_) => return Ok(None),
+        },
+        sdesc::Value::Parameter(ref name) => return Ok(scope.lookup(name)?.cloned()),
+    }))
+}
+
+/// Converts a value outside of a template
+pub(crate) fn convert_value<'a, E>(v: &sdesc::ValueType<'a>) -> Result<Value<E>, syntax::PError<'a>>
+    where E: Extension
+{
+    check_value::<E>(v)?;
+    value(v, &Scope::new(None))
+        .ok()
+        .and_then(|v| v)
+        .ok_or_else(|| syntax::Errors::new(
+            v.position.into(),
+            syntax::Error::Message(syntax::Info::Borrowed("Template parameters can't be converted to a value")),
+        ))
+}
+
+/// Checks that every tagged literal in the document can be
+/// parsed by the extension so that errors are reported before
+/// any nodes are created.
+pub(crate) fn check_literals<'a, E>(doc: &sdesc::Document<'a>) -> Result<(), syntax::PError<'a>>
+    where E: Extension
+{
+    for t in &doc.templates {
+        for v in t.parameters.iter().filter_map(|v| v.1.as_ref()) {
+            check_value::<E>(v)?;
+        }
+        check_element::<E>(&t.root)?;
+    }
+    check_nodes::<E>(&doc.nodes)
+}
+
+fn check_nodes<'a, E>(nodes: &[sdesc::Node<'a>]) -> Result<(), syntax::PError<'a>>
+    where E: Extension
+{
+    for n in nodes {
+        match *n {
+            sdesc::Node::Element(ref e) => check_element::<E>(e)?,
+            sdesc::Node::Text(_, _, ref props) | sdesc::Node::Parameter(_, ref props) => {
+                for v in props.values() {
+                    check_value::<E>(v)?;
+                }
+            },
+            sdesc::Node::If(ref v) => {
+                check_nodes::<E>(&v.nodes)?;
+                check_nodes::<E>(&v.else_nodes)?;
+            },
+            sdesc::Node::For(ref v) => check_nodes::<E>(&v.nodes)?,
+            sdesc::Node::Slot(..) => {},
+        }
+    }
+    Ok(())
+}
+
+fn check_element<'a, E>(e: &sdesc::Element<'a>) -> Result<(), syntax::PError<'a>>
+    where E: Extension
+{
+    for v in e.properties.values() {
+        check_value::<E>(v)?;
+    }
+    check_nodes::<E>(&e.nodes)
+}
+
+fn check_value<'a, E>(v: &sdesc::ValueType<'a>) -> Result<(), syntax::PError<'a>>
+    where E: Extension
+{
+    match v.value {
+        sdesc::Value::Tagged(ref tag, ref val) => {
+            E::parse_literal(&tag.name, &unescape(val))
+                .map_err(|err| syntax::Errors::new(
+                    v.position.into(),
+                    syntax::Error::Message(syntax::Info::Borrowed(err)),
+                ))?;
+        },
+        sdesc::Value::List(ref l) => for v in l {
+            check_value::<E>(v)?;
+        },
+        _ => {},
+    }
+    Ok(())
+}
+
+/// Creates an instance of a template defined in a
+/// description document.
+///
+/// # Example
+///
+/// ```
+/// # extern crate fungui;
+/// # extern crate fungui_syntax;
+/// # use fungui::*;
+/// # enum MyExt {}
+/// # impl Extension for MyExt {
+/// #     type NodeData = ();
+/// #     type Value = ();
+/// #     fn new_data() {}
+/// #     fn style_properties<'a, F>(_prop: F) where F: FnMut(StaticKey) + 'a {}
+/// #     fn update_data(_: &Styles<MyExt>, _: &NodeChain<MyExt>, _: &Rule<MyExt>, _: &mut ()) -> DirtyFlags {
+/// #         DirtyFlags::empty()
+/// #     }
+/// #     fn reset_unset_data(_: &FnvHashSet<StaticKey>, _: &mut ()) -> DirtyFlags {
+/// #         DirtyFlags::empty()
+/// #     }
+/// # }
+/// # fn main() {
+/// let doc = fungui_syntax::desc::Document::parse(r#"
+/// root
+/// @template window(title) {
+///     window {
+///         $title
+///         content { @slot }
+///         footer { @slot(footer) }
+///     }
+/// }
+/// "#).unwrap();
+/// let window: Node<MyExt> = TemplateBuilder::new(&doc, "window")
+///     .expect("Missing template")
+///     .arg("title", "Settings".to_owned())
+///     .child(Node::new("options"))
+///     .slot("footer", Node::new("close_button"))
+///     .build()
+///     .unwrap();
+/// # }
+/// ```
+pub struct TemplateBuilder<'a, E: Extension> {
+    builder: Builder<'a>,
+    template: &'a sdesc::Template<'a>,
+    properties: FnvHashMap<String, Value<E>>,
+    children: Vec<(Option<String>, Node<E>)>,
+    data: Option<&'a DataContext<E>>,
+}
+
+impl <'a, E> TemplateBuilder<'a, E>
+    where E: Extension
+{
+    /// Begins creating an instance of the named template
+    /// from the document.
+    ///
+    /// Returns `None` if the document doesn't contain the template.
+    pub fn new(doc: &'a sdesc::Document<'a>, name: &str) -> Option<TemplateBuilder<'a, E>> {
+        let template = doc.templates.iter().find(|v| v.name.name == name)?;
+        Some(TemplateBuilder {
+            builder: Builder::new(&doc.templates),
+            template,
+            properties: FnvHashMap::default(),
+            children: Vec::new(),
+            data: None,
+        })
+    }
+
+    /// Sets the value of a parameter.
+    ///
+    /// Values that don't match a parameter are set as
+    /// properties on the template's root element.
+    pub fn arg<V>(mut self, key: &str, v: V) -> Self
+        where V: ConvertValue<E>
+    {
+        self.properties.insert(key.into(), V::to_value(v));
+        self
+    }
+
+    /// Adds a node to the template's default slot
+    pub fn child(mut self, node: Node<E>) -> Self {
+        self.children.push((None, node));
+        self
+    }
+
+    /// Adds a node to the named slot
+    pub fn slot(mut self, name: &str, node: Node<E>) -> Self {
+        self.children.push((Some(name.into()), node));
+        self
+    }
+
+    /// Sets the data context used by `@if`, `@for` and
+    /// `$name` within the template
+    pub fn data(mut self, data: &'a DataContext<E>) -> Self {
+        self.data = Some(data);
+        self
+    }
+
+    /// Creates the template's nodes.
+    ///
+    /// Fails if the template uses unknown parameters. See
+    /// [`DataContext`](struct.DataContext.html)
+    pub fn build(mut self) -> Result<Node<E>, syntax::PError<'a>> {
+        self.builder.instantiate(self.template, self.properties, self.children, self.data)
+    }
+}
@@ -1,12 +1,15 @@
+use super::*;
+use std::borrow::Cow;
+
 
 
 /// The error type used in FunGUI
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum Error<'a> {
     /// An unknown variable was used
     UnknownVariable {
         /// The name of the variable
-        name: &'a str,
+        name: Cow<'a, str>,
     },
     /// An incompatible type was used with the given
     /// operator
@@ -43,5 +46,50 @@ pub enum Error<'a> {
         position: i32,
         /// The parameter name
         name: &'static str,
+    },
+    /// A style property was set to a value of the
+    /// wrong type
+    IncorrectType {
+        /// The types the property accepts
+        expected: ValueKind,
+        /// The type of the value
+        found: &'static str,
+    },
+    /// A list was indexed outside of its bounds
+    IndexOutOfRange {
+        /// The index used
+        index: i32,
+        /// The length of the list
+        len: usize,
+    },
+}
+
+impl <'a> Error<'a> {
+    /// Converts the error into one that doesn't borrow
+    /// from the styles
+    pub fn into_owned(self) -> Error<'static> {
+        match self {
+            Error::UnknownVariable{name} => Error::UnknownVariable{name: Cow::Owned(name.into_owned())},
+            Error::IncompatibleTypeOp{op, ty} => Error::IncompatibleTypeOp{op, ty},
+            Error::IncompatibleTypesOp{op, left_ty, right_ty} => Error::IncompatibleTypesOp{op, left_ty, right_ty},
+            Error::Custom{reason} => Error::Custom{reason},
+            Error::CustomStatic{reason} => Error::CustomStatic{reason},
+            Error::MissingParameter{position, name} => Error::MissingParameter{position, name},
+            Error::IncorrectType{expected, found} => Error::IncorrectType{expected, found},
+            Error::IndexOutOfRange{index, len} => Error::IndexOutOfRange{index, len},
+        }
     }
-}
\ No newline at end of file
+}
+
+/// An error produced whilst evaluating a style property.
+///
+/// See `Manager::take_errors`
+#[derive(Debug, PartialEq)]
+pub struct EvalError {
+    /// The style key that was being evaluated
+    pub key: StaticKey,
+    /// The expression that failed
+    pub expr: String,
+    /// The reason it failed
+    pub error: Error<'static>,
+}
@@ -1,5 +1,6 @@
 use super::*;
 use std::fmt::{Formatter, Result as FResult, Display};
+use std::borrow::Cow;
 
 #[derive(Debug)]
 pub enum RectPart {
@@ -7,11 +8,30 @@ pub enum RectPart {
     Height,
 }
 
+bitflags! {
+    /// The built-in variables used by a rule
+    pub(crate) struct UsedVariables: u8 {
+        const PARENT_SIZE   = 0b0000_0001;
+        const SELF_SIZE     = 0b0000_0010;
+        const SCREEN_SIZE   = 0b0000_0100;
+        const INDEX         = 0b0000_1000;
+        const CHILD_COUNT   = 0b0001_0000;
+        const SIBLING_COUNT = 0b0010_0000;
+    }
+}
+
 pub enum Expr<E: Extension> {
     Value(Value<E>),
     Variable(String),
     ParentRect(RectPart),
+    SelfRect(RectPart),
+    ScreenRect(RectPart),
+    Index,
+    ChildCount,
+    SiblingCount,
     VariableParent(usize, String),
+    ParentProperty(String),
+    AncestorProperty(String, String),
 
     Neg(Box<Expr<E>>),
     Not(Box<Expr<E>>),
@@ -36,6 +56,9 @@ pub enum Expr<E: Extension> {
     FloatToInt(Box<Expr<E>>),
 
     Call(StaticKey, Vec<Expr<E>>),
+
+    List(Vec<Expr<E>>),
+    ListIndex(Box<Expr<E>>, Box<Expr<E>>),
 }
 
 impl <E> Display for Expr<E>
@@ -43,14 +66,17 @@ impl <E> Display for Expr<E>
 {
     fn fmt(&self, f: &mut Formatter) -> FResult {
         match self {
-            Expr::Value(Value::Boolean(v)) => write!(f, "{}", v),
-            Expr::Value(Value::Integer(v)) => write!(f, "{}", v),
-            Expr::Value(Value::Float(v)) => write!(f, "{}", v),
-            Expr::Value(Value::String(v)) => write!(f, "{:?}", v),
-            Expr::Value(Value::ExtValue(_)) => write!(f, "EXT"),
+            Expr::Value(v) => fmt_value(f, v),
             Expr::Variable(var) => write!(f, "{}", var),
             Expr::VariableParent(d, var) => write!(f, "{}({})", var, d),
             Expr::ParentRect(part) => write!(f, "parent({:?})", part),
+            Expr::SelfRect(part) => write!(f, "self({:?})", part),
+            Expr::ScreenRect(part) => write!(f, "screen({:?})", part),
+            Expr::Index => write!(f, "index"),
+            Expr::ChildCount => write!(f, "child_count"),
+            Expr::SiblingCount => write!(f, "sibling_count"),
+            Expr::ParentProperty(var) => write!(f, "parent.{}", var),
+            Expr::AncestorProperty(name, var) => write!(f, "ancestor({}).{}", name, var),
 
             Expr::Neg(e) => write!(f, "-({})", e),
             Expr::Not(e) => write!(f, "!({})", e),
@@ -81,16 +107,52 @@ impl <E> Display for Expr<E>
                 }
                 write!(f, ")")
             },
+            Expr::List(exprs) => {
+                write!(f, "[")?;
+                for (idx, e) in exprs.iter().enumerate() {
+                    if idx != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", e)?;
+                }
+                write!(f, "]")
+            },
+            Expr::ListIndex(l, i) => write!(f, "({})[{}]", l, i),
         }
     }
 }
 
-fn get_ty<E: Extension>(v: &Value<E>) -> &'static str {
+fn fmt_value<E: Extension>(f: &mut Formatter, v: &Value<E>) -> FResult {
+    match v {
+        Value::Boolean(v) => write!(f, "{}", v),
+        Value::Integer(v) => write!(f, "{}", v),
+        Value::Float(v) => write!(f, "{}", v),
+        Value::String(v) => write!(f, "{:?}", v),
+        Value::Color(v) => write!(f, "{}", v),
+        Value::Atom(v) => write!(f, "{}", v),
+        Value::List(v) => {
+            write!(f, "[")?;
+            for (idx, v) in v.iter().enumerate() {
+                if idx != 0 {
+                    write!(f, ", ")?;
+                }
+                fmt_value(f, v)?;
+            }
+            write!(f, "]")
+        },
+        Value::ExtValue(_) => write!(f, "EXT"),
+    }
+}
+
+pub(crate) fn get_ty<E: Extension>(v: &Value<E>) -> &'static str {
     match v {
         Value::Integer(_) => "integer",
         Value::Float(_) => "float",
         Value::Boolean(_) => "boolean",
         Value::String(_) => "string",
+        Value::Color(_) => "colour",
+        Value::Atom(_) => "atom",
+        Value::List(_) => "list",
         Value::ExtValue(_) => "extension value",
     }
 }
@@ -101,13 +163,29 @@ impl <E> Expr<E>
     pub fn eval<'a>(&'a self, styles: &'a Styles<E>, node: &'a NodeChain<E>) -> Result<Value<E>, Error<'a>> {
         Ok(match *self {
             Expr::Value(ref v) => v.clone(),
-            Expr::Variable(ref n) => return node.properties.get(n).cloned().ok_or(Error::UnknownVariable{name: n}),
+            Expr::Variable(ref n) => return node.properties.get(n).cloned().ok_or(Error::UnknownVariable{name: Cow::Borrowed(n)}),
             Expr::VariableParent(depth, ref n) => {
                 let mut node = node;
                 for _ in 0 .. depth {
                     node = node.parent.expect("Missing parent, shouldn't happen");
                 }
-                return node.properties.get(n).cloned().ok_or(Error::UnknownVariable{name: n});
+                return node.properties.get(n).cloned().ok_or(Error::UnknownVariable{name: Cow::Borrowed(n)});
+            },
+            Expr::ParentProperty(ref n) => return node.parent
+                .and_then(|v| v.properties.get(n))
+                .cloned()
+                .ok_or(Error::UnknownVariable{name: Cow::Borrowed(n)}),
+            Expr::AncestorProperty(ref name, ref n) => {
+                let mut cur = node.parent;
+                while let Some(p) = cur {
+                    if let NCValue::Element(ref e) = p.value {
+                        if e == name {
+                            return p.properties.get(n).cloned().ok_or(Error::UnknownVariable{name: Cow::Borrowed(n)});
+                        }
+                    }
+                    cur = p.parent;
+                }
+                return Err(Error::UnknownVariable{name: Cow::Borrowed(n)});
             },
             Expr::ParentRect(RectPart::Width) => return node.parent
                 .ok_or(Error::CustomStatic{reason: "No parent"})
@@ -117,6 +195,16 @@ impl <E> Expr<E>
                 .ok_or(Error::CustomStatic{reason: "No parent"})
                 .map(|v| v.draw_rect.height)
                 .map(Value::Integer),
+            Expr::SelfRect(RectPart::Width) => Value::Integer(node.draw_rect.width),
+            Expr::SelfRect(RectPart::Height) => Value::Integer(node.draw_rect.height),
+            Expr::ScreenRect(RectPart::Width) => Value::Integer(styles.screen_size.0),
+            Expr::ScreenRect(RectPart::Height) => Value::Integer(styles.screen_size.1),
+            Expr::Index => Value::Integer(node.index as i32),
+            Expr::ChildCount => Value::Integer(node.child_count as i32),
+            Expr::SiblingCount => return node.parent
+                .ok_or(Error::CustomStatic{reason: "No parent"})
+                .map(|v| v.child_count as i32 - 1)
+                .map(Value::Integer),
             Expr::Neg(ref e) => match e.eval(styles, node)? {
                 Value::Integer(a) => Value::Integer(-a),
                 Value::Float(a) => Value::Float(-a),
@@ -204,119 +292,160 @@ impl <E> Expr<E>
                 let mut args = args.iter()
                     .map(move |v| v.eval(styles, node));
                 return func(&mut args)
-            }
+            },
+            Expr::List(ref v) => Value::List(v.iter()
+                .map(|v| v.eval(styles, node))
+                .collect::<Result<_, _>>()?),
+            Expr::ListIndex(ref l, ref i) => match (l.eval(styles, node)?, i.eval(styles, node)?) {
+                (Value::List(mut l), Value::Integer(i)) => if i >= 0 && (i as usize) < l.len() {
+                    l.swap_remove(i as usize)
+                } else {
+                    return Err(Error::IndexOutOfRange{index: i, len: l.len()});
+                },
+                (a,b) => return Err(Error::IncompatibleTypesOp{op: "[]", left_ty: get_ty(&a), right_ty: get_ty(&b)}),
+            },
         })
     }
 
-    pub fn from_style<'a>(
+    pub(crate) fn from_style<'a>(
         static_keys: &FnvHashMap<&'static str, StaticKey>,
         replacements: &FnvHashMap<String, (usize, String)>,
-        uses_parent_size: &mut bool,
+        used_variables: &mut UsedVariables,
+        atoms: Option<&FnvHashSet<&'static str>>,
         e: syntax::style::ExprType<'a>
     ) -> Result<Expr<E>, syntax::PError<'a>> {
         use syntax::style::Expr as SExpr;
         use syntax::style::Value as SVal;
+        let position = e.position;
         Ok(match e.expr {
             SExpr::Value(v) => match v {
                 SVal::Boolean(b) => Expr::Value(Value::Boolean(b)),
                 SVal::Integer(i) => Expr::Value(Value::Integer(i)),
                 SVal::Float(f) => Expr::Value(Value::Float(f)),
-                SVal::String(s) => Expr::Value(Value::String(unescape(s))),
-                SVal::Variable(v) => if let Some(r) = replacements.get(v.name) {
+                SVal::String(s) => Expr::Value(Value::String(unescape(&s))),
+                SVal::Color(c) => Expr::Value(Value::Color(c)),
+                SVal::Tagged(t, s) => Expr::Value(Value::ExtValue(E::parse_literal(&t.name, &unescape(&s))
+                    .map_err(|err| syntax::Errors::new(
+                        position.into(),
+                        syntax::Error::Message(syntax::Info::Borrowed(err)),
+                    ))?)),
+                SVal::Variable(v) => if let Some(r) = replacements.get(&*v.name) {
                     if r.0 == 0 {
                         Expr::Variable(r.1.clone())
                     } else {
                         Expr::VariableParent(r.0, r.1.clone())
                     }
                 } else {
-                    *uses_parent_size = true;
-                    match v.name {
-                        "parent_width" => Expr::ParentRect(RectPart::Width),
-                        "parent_height" => Expr::ParentRect(RectPart::Height),
+                    let (var, expr) = match &*v.name {
+                        "parent_width" => (UsedVariables::PARENT_SIZE, Expr::ParentRect(RectPart::Width)),
+                        "parent_height" => (UsedVariables::PARENT_SIZE, Expr::ParentRect(RectPart::Height)),
+                        "self_width" => (UsedVariables::SELF_SIZE, Expr::SelfRect(RectPart::Width)),
+                        "self_height" => (UsedVariables::SELF_SIZE, Expr::SelfRect(RectPart::Height)),
+                        "screen_width" => (UsedVariables::SCREEN_SIZE, Expr::ScreenRect(RectPart::Width)),
+                        "screen_height" => (UsedVariables::SCREEN_SIZE, Expr::ScreenRect(RectPart::Height)),
+                        "index" => (UsedVariables::INDEX, Expr::Index),
+                        "child_count" => (UsedVariables::CHILD_COUNT, Expr::ChildCount),
+                        "sibling_count" => (UsedVariables::SIBLING_COUNT, Expr::SiblingCount),
+                        // Atoms allowed by the style key
+                        name if atoms.and_then(|v| v.get(name)).is_some() => {
+                            return Ok(Expr::Value(Value::Atom(v.name.into_owned())));
+                        },
                         _ => return Err(syntax::Errors::new(
                             v.position.into(),
                             syntax::Error::Message(syntax::Info::Borrowed("Unknown variable")),
-                        ))
-                    }
+                        )),
+                    };
+                    *used_variables |= var;
+                    expr
                 },
             },
-            SExpr::Neg(e) => Expr::Neg(Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *e)?)),
+            SExpr::ParentProperty(n) => Expr::ParentProperty(n.name.into()),
+            SExpr::AncestorProperty(e, n) => Expr::AncestorProperty(e.name.into(), n.name.into()),
+            SExpr::Neg(e) => Expr::Neg(Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *e)?)),
 
-            SExpr::Not(e) => Expr::Not(Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *e)?)),
+            SExpr::Not(e) => Expr::Not(Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *e)?)),
             SExpr::And(l, r) => Expr::And(
-                Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *l)?),
-                Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *r)?),
+                Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *l)?),
+                Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *r)?),
             ),
             SExpr::Or(l, r) => Expr::Or(
-                Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *l)?),
-                Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *r)?),
+                Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *l)?),
+                Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *r)?),
             ),
             SExpr::Xor(l, r) => Expr::Xor(
-                Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *l)?),
-                Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *r)?),
+                Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *l)?),
+                Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *r)?),
             ),
 
             SExpr::Add(l, r) => Expr::Add(
-                Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *l)?),
-                Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *r)?),
+                Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *l)?),
+                Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *r)?),
             ),
             SExpr::Sub(l, r) => Expr::Sub(
-                Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *l)?),
-                Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *r)?),
+                Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *l)?),
+                Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *r)?),
             ),
             SExpr::Mul(l, r) => Expr::Mul(
-                Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *l)?),
-                Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *r)?),
+                Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *l)?),
+                Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *r)?),
             ),
             SExpr::Div(l, r) => Expr::Div(
-                Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *l)?),
-                Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *r)?),
+                Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *l)?),
+                Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *r)?),
             ),
             SExpr::Rem(l, r) => Expr::Rem(
-                Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *l)?),
-                Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *r)?),
+                Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *l)?),
+                Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *r)?),
             ),
 
             SExpr::Equal(l, r) => Expr::Equal(
-                Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *l)?),
-                Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *r)?),
+                Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *l)?),
+                Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *r)?),
             ),
             SExpr::NotEqual(l, r) => Expr::NotEqual(
-                Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *l)?),
-                Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *r)?),
+                Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *l)?),
+                Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *r)?),
             ),
             SExpr::LessEqual(l, r) => Expr::LessEqual(
-                Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *l)?),
-                Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *r)?),
+                Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *l)?),
+                Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *r)?),
             ),
             SExpr::GreaterEqual(l, r) => Expr::GreaterEqual(
-                Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *l)?),
-                Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *r)?),
+                Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *l)?),
+                Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *r)?),
             ),
             SExpr::Less(l, r) => Expr::Less(
-                Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *l)?),
-                Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *r)?),
+                Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *l)?),
+                Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *r)?),
             ),
             SExpr::Greater(l, r) => Expr::Greater(
-                Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *l)?),
-                Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *r)?),
+                Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *l)?),
+                Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *r)?),
             ),
 
-            SExpr::IntToFloat(e) => Expr::IntToFloat(Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *e)?)),
-            SExpr::FloatToInt(e) => Expr::FloatToInt(Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *e)?)),
+            SExpr::IntToFloat(e) => Expr::IntToFloat(Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *e)?)),
+            SExpr::FloatToInt(e) => Expr::FloatToInt(Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *e)?)),
 
             SExpr::Call(name, params) => {
-                let key = static_keys.get(name.name).ok_or_else(|| {
+                let key = static_keys.get(&*name.name).ok_or_else(|| {
                     syntax::Errors::new(
                         name.position.into(),
                         syntax::Error::Message(syntax::Info::Borrowed("Unknown function")),
                     )
                 })?;
                 Expr::Call(*key, params.into_iter()
-                    .map(|v| Expr::from_style(static_keys, replacements, uses_parent_size, v))
+                    .map(|v| Expr::from_style(static_keys, replacements, used_variables, atoms, v))
                     .collect::<Result<Vec<_>, _>>()?
                 )
             },
+            SExpr::List(v) => Expr::List(v.into_iter()
+                .map(|v| Expr::from_style(static_keys, replacements, used_variables, atoms, v))
+                .collect::<Result<Vec<_>, _>>()?
+            ),
+            SExpr::Index(l, i) => Expr::ListIndex(
+                Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *l)?),
+                Box::new(Expr::from_style(static_keys, replacements, used_variables, atoms, *i)?),
+            ),
 
         })
     }
@@ -52,6 +52,15 @@ pub trait LayoutEngine<E>
     fn style_properties<'a, F>(prop: F)
         where F: FnMut(StaticKey) + 'a;
 
+    /// Called to declare the types of value that the layout's
+    /// properties accept.
+    ///
+    /// See `Extension::style_types`
+    fn style_types<'a, F>(_ty: F)
+        where F: FnMut(StaticKey, ValueKind) + 'a
+    {
+    }
+
     /// Creates a new child data to be stored on a node
     fn new_child_data() -> Self::ChildData;
 
@@ -348,6 +357,14 @@ impl <E> LayoutEngine<E> for AbsoluteLayout
         prop(WIDTH);
         prop(HEIGHT);
     }
+    fn style_types<'a, F>(mut ty: F)
+        where F: FnMut(StaticKey, ValueKind) + 'a
+    {
+        ty(X, ValueKind::NUMBER);
+        ty(Y, ValueKind::NUMBER);
+        ty(WIDTH, ValueKind::NUMBER);
+        ty(HEIGHT, ValueKind::NUMBER);
+    }
 
     fn new_child_data() -> AbsoluteLayoutChild {
         AbsoluteLayoutChild::default()
@@ -58,7 +58,8 @@
 //! Styles apply using matching rules to find what nodes they will apply too. Rules
 //! can specific a hierarchy of nodes and what properties the node should have and
 //! their values. This allows for a `title` inside an `alert` to act differently to
-//! a `title` inside an `window` for example.
+//! a `title` inside an `window` for example. Multiple rules can share the same
+//! styles by separating them with a comma (`button, toggle > @text`).
 //!
 //! Once a match is found the style rules are applied to the node. Rules can be a
 //! simple constant value or an expression. Expressions perform basic math (`+-/*%`)
@@ -66,6 +67,38 @@
 //! and execute functions. Functions can be used for complex properties instead of
 //! spliting them across multiple rules.
 //!
+//! ## Rule precedence
+//!
+//! When multiple rules set the same style property on a node only one of them is
+//! used. Rules are ordered by the following, with the first difference deciding
+//! the winner:
+//!
+//! 1. Whether the rule is scoped to a node (via `Manager::load_scoped_styles`).
+//!    Scoped rules always win over unscoped ones and rules scoped to a closer
+//!    ancestor win over ones scoped further up.
+//! 2. The priority of the set of styles the rule was loaded into (via
+//!    `Manager::set_styles_priority`). Higher wins, sets with the same priority
+//!    are ordered by when they were first loaded with later winning.
+//! 3. The explicit priority of the rule, set by prefixing the rule with
+//!    `@priority(n)`. Rules without one have a priority of `0`. Higher wins.
+//! 4. The number of property matchers in the rule (`button(focused=true)` has
+//!    one). More wins.
+//! 5. The number of nodes in the hierarchy the rule matches (`panel > button`
+//!    matches two). More wins.
+//! 6. The order the rules were loaded in. Later wins.
+//!
+//! This means a `@priority(100)` rule still loses to any rule in a set with a
+//! higher priority.
+//!
+//! ## Inherited properties
+//!
+//! Extensions can mark style properties as inherited (via
+//! `Extension::inherited_properties`), as can the program for core and layout
+//! properties (via `Manager::add_inherited_property`). When no rule sets an
+//! inherited property on a node, the value used by its parent is used instead.
+//! This allows things like a font color set on a container to apply to all the
+//! text inside it without a `> @text` rule at every depth.
+//!
 //! ## Variables and types
 //!
 //! Variables are typed and floats/integers are treated as seperate and not casted
@@ -73,6 +106,11 @@
 //! defining a number as `5` will be an integer whilst `5.0` will be a float. For
 //! variables you can cast using `int(val)` or `float(val)`.
 //!
+//! Extensions and layouts can declare the types each of their style properties
+//! accept (via `style_types`). Constant values of the wrong type fail to load
+//! and expressions that evaluate to the wrong type are reported via
+//! `Manager::take_errors` along with any other evaluation errors.
+//!
 //! ### Special variables
 //!
 //! There are two special variables that can be used without using them in a matching
@@ -83,6 +121,31 @@
 //! causing a slowdown however this will generally only happen the first time the
 //! node has its layout computed.
 //!
+//! The following are also available:
+//!
+//! * `self_width`/`self_height` - The size of the node from its last layout. Changes
+//!   are only picked up by the next call to `Manager::layout` and these shouldn't be
+//!   used to compute the node's own size.
+//! * `screen_width`/`screen_height` - The size passed to `Manager::layout`.
+//! * `index` - The position of the node within its parent.
+//! * `child_count` - The number of children the node has.
+//! * `sibling_count` - The number of other children the node's parent has.
+//!
+//! Only nodes with rules using these variables are updated when they change.
+//!
+//! ### Ancestor properties
+//!
+//! Properties on the parent can be read via `parent.name` and properties on the
+//! nearest ancestor element with a given name via `ancestor(element).name`. Unlike
+//! variables these don't need to be matched by the rule's selector. Using a
+//! property that the node doesn't have is an error.
+//!
+//! ```text,ignore
+//! dialog > content {
+//!     width = ancestor(dialog).content_width,
+//! }
+//! ```
+//!
 //! ## Example
 //!
 //! An example of the style format:
@@ -154,10 +217,11 @@
 //!     tint = rgba(0, 255, 255, 0.4),
 //! }
 //! button(on_click=click) {
-//!     on_mouse_up = list(click, "init#
-//!         audio.play_sound('click')
+//!     // Raw strings don't need their quotes escaped
+//!     on_mouse_up = list(click, r#"init#
+//!         audio.play_sound("click")
 //!         return true
-//!     "),
+//!     "#),
 //! }
 //! ```
 
@@ -172,7 +236,7 @@ extern crate bitflags;
 mod query;
 pub use query::Query;
 mod error;
-pub use error::Error;
+pub use error::{Error, EvalError};
 #[macro_use]
 mod macros;
 #[cfg(any(test, feature="tests"))]
@@ -181,6 +245,10 @@ mod style;
 use style::*;
 mod expr;
 use expr::*;
+mod desc;
+pub use desc::{DataContext, TemplateBuilder};
+mod resource;
+pub use resource::ResourceResolver;
 mod layout;
 use layout::*;
 
@@ -199,8 +267,9 @@ use std::rc::{Rc, Weak};
 use std::cell::{Ref, RefMut, RefCell};
 use std::any::Any;
 use std::hash::{Hash, Hasher};
+use std::fmt;
 use bitflags::bitflags;
-pub use syntax::{format_error, format_parse_error};
+pub use syntax::{format_error, format_parse_error, Color, FileError};
 
 /// An alias for a common return type used in FunGUI
 pub type FResult<'a, T> = Result<T, Error<'a>>;
@@ -268,6 +337,48 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// The types of value that a style property accepts
+    pub struct ValueKind: u32 {
+        /// `Value::Boolean`
+        const BOOLEAN   = 0b0000_0001;
+        /// `Value::Integer`
+        const INTEGER   = 0b0000_0010;
+        /// `Value::Float`
+        const FLOAT     = 0b0000_0100;
+        /// `Value::String`
+        const STRING    = 0b0000_1000;
+        /// `Value::Color`
+        const COLOR     = 0b0001_0000;
+        /// `Value::Atom`
+        const ATOM      = 0b0010_0000;
+        /// `Value::List`
+        const LIST      = 0b0100_0000;
+        /// `Value::ExtValue`
+        const EXT_VALUE = 0b1000_0000;
+        /// Either an integer or a float
+        const NUMBER    = Self::INTEGER.bits | Self::FLOAT.bits;
+        /// Either a string or an atom
+        const TEXT      = Self::STRING.bits | Self::ATOM.bits;
+    }
+}
+
+impl ValueKind {
+    /// Returns the kind of the passed value
+    pub fn of<E: Extension>(v: &Value<E>) -> ValueKind {
+        match *v {
+            Value::Boolean(_) => ValueKind::BOOLEAN,
+            Value::Integer(_) => ValueKind::INTEGER,
+            Value::Float(_) => ValueKind::FLOAT,
+            Value::String(_) => ValueKind::STRING,
+            Value::Color(_) => ValueKind::COLOR,
+            Value::Atom(_) => ValueKind::ATOM,
+            Value::List(_) => ValueKind::LIST,
+            Value::ExtValue(_) => ValueKind::EXT_VALUE,
+        }
+    }
+}
+
 /// Extensions extend stylish to allow custom style properties to be added
 pub trait Extension {
     /// The type of the data that will be stored on every node
@@ -301,6 +412,64 @@ pub trait Extension {
     fn style_properties<'a, F>(prop: F)
         where F: FnMut(StaticKey) + 'a;
 
+    /// Called to mark style keys as inherited.
+    ///
+    /// When no rule sets an inherited key on a node the value
+    /// computed for the node's parent is used instead. This is
+    /// useful for properties like font colors that should apply
+    /// to all text within an element.
+    ///
+    /// The keys must also be registered via `style_properties`.
+    ///
+    /// # Example
+    /// ```ignore
+    /// fn inherited_properties<'a, F>(mut prop: F)
+    ///     where F: FnMut(StaticKey) + 'a
+    /// {
+    ///     prop(FONT_COLOR);
+    /// }
+    /// ```
+    fn inherited_properties<'a, F>(_prop: F)
+        where F: FnMut(StaticKey) + 'a
+    {
+    }
+
+    /// Called to declare the types of value that style keys accept.
+    ///
+    /// Keys without a declared type accept any value.
+    ///
+    /// # Example
+    /// ```ignore
+    /// fn style_types<'a, F>(mut ty: F)
+    ///     where F: FnMut(StaticKey, ValueKind) + 'a
+    /// {
+    ///     ty(FONT_COLOR, ValueKind::COLOR);
+    /// }
+    /// ```
+    fn style_types<'a, F>(_ty: F)
+        where F: FnMut(StaticKey, ValueKind) + 'a
+    {
+    }
+
+    /// Called to parse a tagged literal (e.g. `img"ui/button"`)
+    /// into an extension value.
+    ///
+    /// The returned error is reported at the literal's position.
+    /// By default every tag is rejected.
+    ///
+    /// # Example
+    /// ```ignore
+    /// fn parse_literal(tag: &str, value: &str) -> Result<Self::Value, &'static str> {
+    ///     match tag {
+    ///         "img" => Ok(MyValue::Image(value.to_owned())),
+    ///         _ => Err("Unknown literal tag"),
+    ///     }
+    /// }
+    /// ```
+    fn parse_literal(_tag: &str, _value: &str) -> Result<Self::Value, &'static str> {
+        Err("Unknown literal tag")
+    }
+
     /// Called to apply a given style rule on a node
     ///
     /// Its recomended to use the `eval!` macro to check for relevant properties
@@ -350,7 +519,6 @@ pub struct Manager<E: Extension> {
     // in the system
     root: Node<E>,
     styles: Styles<E>,
-    last_size: (i32, i32),
     dirty: bool,
 }
 
@@ -371,21 +539,56 @@ impl<E: Extension> Manager<E> {
             prop(LAYOUT);
             E::style_properties(prop);
         }
+        let mut inherited_keys = FnvHashSet::default();
+        E::inherited_properties(|key| {inherited_keys.insert(key);});
+        let mut key_types = FnvHashMap::default();
+        {
+            let mut ty = |key: StaticKey, kind: ValueKind| {
+                *key_types.entry(key).or_insert_with(ValueKind::empty) |= kind;
+            };
+            ty(CLIP_OVERFLOW, ValueKind::BOOLEAN);
+            ty(SCROLL_X, ValueKind::NUMBER);
+            ty(SCROLL_Y, ValueKind::NUMBER);
+            ty(LAYOUT, ValueKind::TEXT);
+            E::style_types(ty);
+        }
         let mut m = Manager {
             root: Node::root(),
             styles: Styles {
                 _ext: ::std::marker::PhantomData,
                 static_keys,
-                rules: Rules::new(),
+                layers: Vec::new(),
+                inherited_keys,
+                screen_size: (0, 0),
                 funcs: FnvHashMap::default(),
                 layouts: FnvHashMap::default(),
+                atoms: FnvHashMap::default(),
+                key_types,
+                errors: RefCell::new(Vec::new()),
+                inherited_values: RefCell::new(FnvHashMap::default()),
                 next_rule_id: 0,
+                next_layer_id: 0,
                 used_keys: FnvHashSet::default(),
             },
-            last_size: (0, 0),
             dirty: true,
         };
         m.add_layout_engine(AbsoluteLayout::default);
+        m.add_func_raw("len", |args| {
+            let len = match args.next()
+                .ok_or(Error::MissingParameter {
+                    position: 0,
+                    name: "list",
+                })
+                .and_then(|v| v)?
+            {
+                Value::List(l) => l.len(),
+                Value::String(s) => s.chars().count(),
+                _ => return Err(Error::CustomStatic {
+                    reason: "Expected a list or string",
+                }),
+            };
+            Ok(Value::Integer(len as i32))
+        });
 
         m
     }
@@ -395,13 +598,56 @@ impl<E: Extension> Manager<E> {
     /// A layout engine is used to position elements within an element.
     ///
     /// The layout engine can be selected by using the `layout` attribute.
+    /// The layout's name is also allowed as an atom (`layout = rows`).
     pub fn add_layout_engine<F, L>(&mut self, creator: F)
     where
         F: Fn() -> L + 'static,
         L: LayoutEngine<E> + 'static,
     {
         L::style_properties(|key| {self.styles.static_keys.insert(key.0, key);});
+        L::style_types(|key, kind| {
+            *self.styles.key_types.entry(key).or_insert_with(ValueKind::empty) |= kind;
+        });
         self.styles.layouts.insert(L::name(), Box::new(move || Box::new(creator())));
+        self.add_atoms(LAYOUT, &[L::name()]);
+    }
+
+    /// Returns and clears the errors from evaluating style
+    /// properties during previous calls to `layout`.
+    ///
+    /// Includes values that don't match the type declared
+    /// for their key. Duplicate errors are only reported once
+    /// and at most 256 errors are kept between calls.
+    pub fn take_errors(&mut self) -> Vec<EvalError> {
+        ::std::mem::take(self.styles.errors.get_mut())
+    }
+
+    /// Marks the named style property as inherited.
+    ///
+    /// This allows core and layout properties (e.g. `clip_overflow`
+    /// or `width`) to be inherited as well as the ones marked via
+    /// `Extension::inherited_properties`. Returns `false` if the
+    /// property hasn't been registered.
+    pub fn add_inherited_property(&mut self, name: &str) -> bool {
+        match self.styles.static_keys.get(name) {
+            Some(key) => {
+                self.styles.inherited_keys.insert(*key);
+                self.dirty = true;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Adds to the atoms (bare identifiers such as `center`) that
+    /// can be used as the value of the style property.
+    ///
+    /// Identifiers that aren't variables or atoms allowed for the
+    /// property fail to load as unknown variables.
+    pub fn add_atoms(&mut self, key: StaticKey, atoms: &[&'static str]) {
+        self.styles.atoms.entry(key)
+            .or_default()
+            .extend(atoms);
     }
 
     /// Add a function that can be called by style rules
@@ -425,6 +671,24 @@ impl<E: Extension> Manager<E> {
         Ok(())
     }
 
+    /// Adds all the top level nodes in the passed string
+    /// to the root node of this manager.
+    ///
+    /// See [`fragment_from_str`](struct.Node.html#fragment_from_str)
+    pub fn add_nodes_str<'a>(&mut self, nodes: &'a str) -> Result<(), syntax::PError<'a>> {
+        self.add_nodes_str_with_data(nodes, &DataContext::new())
+    }
+
+    /// Adds all the top level nodes in the passed string
+    /// to the root node of this manager using the data
+    /// context for `@if`, `@for` and `$name`.
+    pub fn add_nodes_str_with_data<'a>(&mut self, nodes: &'a str, data: &DataContext<E>) -> Result<(), syntax::PError<'a>> {
+        for node in Node::fragment_from_str_with_data(nodes, data)? {
+            self.add_node(node);
+        }
+        Ok(())
+    }
+
     /// Adds the node to the root node of this manager
     pub fn add_node(&mut self, node: Node<E>) {
         self.root.add_child(node);
@@ -452,22 +716,144 @@ impl<E: Extension> Manager<E> {
 
     /// Loads a set of styles from the given string.
     ///
-    /// The name can be used to remove the loaded styles later
+    /// The name can be used to remove the loaded styles later.
+    /// Each name is its own layer of styles, loading more styles
+    /// with the same name adds to that layer.
     pub fn load_styles<'a>(
         &mut self,
         name: &str,
         style_rules: &'a str,
     ) -> Result<(), syntax::PError<'a>> {
         let styles = syntax::style::Document::parse(style_rules)?;
-        self.styles.load_styles(name, styles)?;
-        self.dirty = true;
+        // Even on failure some rules may have been added
+        let mut keys = FnvHashSet::default();
+        let ret = self.styles.load_styles(name, styles, &mut keys);
+        self.root.mark_styles_dirty(&keys);
+        ret
+    }
+
+    /// Loads a set of styles from the file at the given path
+    /// along with any files it imports.
+    ///
+    /// Files are loaded via the passed resolver. Mixins can be
+    /// shared between files. See [`load_styles`](#method.load_styles)
+    pub fn load_styles_file<R>(
+        &mut self,
+        name: &str,
+        path: &str,
+        resolver: &mut R,
+    ) -> Result<(), FileError>
+        where R: ResourceResolver + ?Sized
+    {
+        let sources = resource::load_sources(resolver, path)?;
+        // Even on failure some rules may have been added
+        let mut keys = FnvHashSet::default();
+        let ret = self.styles.load_styles_sources(name, sources, &mut keys);
+        self.root.mark_styles_dirty(&keys);
+        ret
+    }
+
+    /// Replaces the set of styles with the given name with the
+    /// styles from the given string.
+    ///
+    /// The replacement is atomic, if the new styles fail to load
+    /// the old styles are kept. Useful for switching themes.
+    pub fn replace_styles<'a>(
+        &mut self,
+        name: &str,
+        style_rules: &'a str,
+    ) -> Result<(), syntax::PError<'a>> {
+        let styles = syntax::style::Document::parse(style_rules)?;
+        let keys = self.styles.replace_styles(name, styles)?;
+        self.root.mark_styles_dirty(&keys);
+        Ok(())
+    }
+
+    /// Replaces the set of styles with the given name with the
+    /// styles from the file at the given path along with any
+    /// files it imports.
+    ///
+    /// See [`replace_styles`](#method.replace_styles)
+    pub fn replace_styles_file<R>(
+        &mut self,
+        name: &str,
+        path: &str,
+        resolver: &mut R,
+    ) -> Result<(), FileError>
+        where R: ResourceResolver + ?Sized
+    {
+        let sources = resource::load_sources(resolver, path)?;
+        let keys = self.styles.replace_styles_sources(name, sources)?;
+        self.root.mark_styles_dirty(&keys);
         Ok(())
     }
 
     /// Removes the set of styles with the given name
     pub fn remove_styles(&mut self, name: &str) {
-        self.styles.rules.remove_all_by_name(name);
-        self.dirty = true;
+        let keys = self.styles.remove_layer(name);
+        self.root.mark_styles_dirty(&keys);
+    }
+
+    /// Sets the priority of the set of styles with the given
+    /// name.
+    ///
+    /// Rules in a set with a higher priority always take
+    /// precedence over rules in a set with a lower one. Sets
+    /// with the same priority are ordered by when they were
+    /// first loaded. Defaults to 0.
+    ///
+    /// Returns false if no styles with the name have been loaded.
+    pub fn set_styles_priority(&mut self, name: &str, priority: i32) -> bool {
+        if let Some(keys) = self.styles.set_layer_priority(name, priority) {
+            self.root.mark_styles_dirty(&keys);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Enables or disables the set of styles with the given name
+    /// without removing them.
+    ///
+    /// Returns false if no styles with the name have been loaded.
+    pub fn set_styles_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        if let Some(keys) = self.styles.set_layer_enabled(name, enabled) {
+            self.root.mark_styles_dirty(&keys);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Loads a set of styles from the given string that only
+    /// apply to the passed node and its children.
+    ///
+    /// Scoped styles take precedence over styles loaded via
+    /// `load_styles` and styles scoped to a node take precedence
+    /// over styles scoped to its parents. The styles are stored
+    /// on the node so are removed along with it.
+    pub fn load_scoped_styles<'a>(
+        &mut self,
+        node: &Node<E>,
+        style_rules: &'a str,
+    ) -> Result<(), syntax::PError<'a>> {
+        let styles = syntax::style::Document::parse(style_rules)?;
+        let mut rules = node.inner.borrow_mut().scoped_rules.take()
+            .unwrap_or_else(Rules::new);
+        let ret = self.styles.load_rules(&mut rules, styles);
+        // Even on failure some rules may have been added
+        let keys = rules.root_keys();
+        node.inner.borrow_mut().scoped_rules = Some(rules);
+        node.mark_styles_dirty(&keys);
+        ret
+    }
+
+    /// Removes any styles scoped to the passed node
+    pub fn remove_scoped_styles(&mut self, node: &Node<E>) {
+        let rules = node.inner.borrow_mut().scoped_rules.take();
+        if let Some(rules) = rules {
+            node.mark_styles_dirty(&rules.root_keys());
+        }
     }
 
     /// Positions the nodes in this manager.
@@ -476,8 +862,18 @@ impl<E: Extension> Manager<E> {
     /// position them based on their selected layout.
     pub fn layout(&mut self, width: i32, height: i32) {
         let size = (width, height);
-        let flags = if self.last_size != size {
-            self.last_size = size;
+        let flags = if self.styles.screen_size != size {
+            self.styles.screen_size = size;
+            // Only nodes that use the size need to be updated
+            if let NodeValue::Element(ref v) = self.root.inner.borrow().value {
+                for c in &v.children {
+                    c.mark_variables_changed(UsedVariables::SCREEN_SIZE);
+                    let mut c = c.inner.borrow_mut();
+                    if c.used_variables.contains(UsedVariables::PARENT_SIZE) {
+                        c.properties_changed = true;
+                    }
+                }
+            }
             DirtyFlags::SIZE
         } else {
             DirtyFlags::empty()
@@ -486,11 +882,20 @@ impl<E: Extension> Manager<E> {
         let mut inner = self.root.inner.borrow_mut();
         inner.draw_rect = Rect{x: 0, y: 0, width, height};
 
+        let child_count = if let NodeValue::Element(ref v) = inner.value {
+            v.children.len()
+        } else {
+            0
+        };
         let p = NodeChain {
             parent: None,
             value: NCValue::Element("root"),
             draw_rect: inner.draw_rect,
+            index: 0,
+            child_count,
             properties: &FnvHashMap::default(),
+            inherited: &FnvHashMap::default(),
+            scoped_rules: None,
         };
 
         let mut layout = AbsoluteLayout::default();
@@ -503,8 +908,8 @@ impl<E: Extension> Manager<E> {
             let mut properties_changed = false;
 
             if let NodeValue::Element(ref v) = inner.value {
-                for c in &v.children {
-                    c.do_update(&mut self.styles, &p, &mut layout, self.dirty, flags == DirtyFlags::SIZE, flags);
+                for (idx, c) in v.children.iter().enumerate() {
+                    c.do_update(&mut self.styles, &p, idx, &mut layout, self.dirty, false, flags);
                 }
 
                 for c in &v.children {
@@ -584,14 +989,7 @@ macro_rules! eval {
     ($styles:expr, $n:expr, $rule:ident.$key:expr => $ret:ident => $ok:block) => {
         if !$styles.key_was_used(&$key) {
             if let Some(e) = $rule.styles.get(&$key) {
-                match e.eval($styles, &$n) {
-                    Ok($ret) => $ok,
-                    Err(err) => {
-                        // TODO: Collect errors for the user to display
-                        // instead of printing
-                        println!("Failed to evalulate expression ({}): {:?}", e, err);
-                    }
-                }
+                if let Some($ret) = $styles.eval_key(&$key, e, &$n) $ok
             }
         }
     };
@@ -599,10 +997,12 @@ macro_rules! eval {
 
 impl<E: Extension> Node<E> {
 
+    #[allow(clippy::too_many_arguments)]
     fn do_update(
         &self,
         styles: &mut Styles<E>,
         parent: &NodeChain<E>,
+        index: usize,
         parent_layout: &mut dyn BoxLayoutEngine<E>,
         mut styles_updated: bool, mut parent_dirty: bool,
         parent_flags: DirtyFlags,
@@ -612,7 +1012,13 @@ impl<E: Extension> Node<E> {
 
         let inner: &mut _ = &mut *self.inner.borrow_mut();
         let props_dirty = replace(&mut inner.properties_changed, false);
+        let child_count = if let NodeValue::Element(ref v) = inner.value {
+            v.children.len()
+        } else {
+            0
+        };
         let rules_dirty = replace(&mut inner.rules_dirty, false);
+        let styles_dirty = replace(&mut inner.styles_dirty, false);
         inner.dirty_flags = DirtyFlags::empty();
         if inner.text_changed {
             inner.dirty_flags |= DirtyFlags::TEXT;
@@ -621,17 +1027,23 @@ impl<E: Extension> Node<E> {
             inner.dirty_flags |= DirtyFlags::CHILDREN;
         }
 
-        if styles_updated || rules_dirty {
+        if rules_dirty {
             styles_updated = true;
+        }
+        if styles_updated || styles_dirty {
             parent_dirty = true;
             inner.possible_rules.clear();
             let c = NodeChain {
                 parent: Some(parent),
                 value: inner.value.as_chain(),
                 draw_rect: inner.draw_rect,
+                index,
+                child_count,
                 properties: &inner.properties,
+                inherited: &inner.inherited,
+                scoped_rules: inner.scoped_rules.as_ref(),
             };
-            styles.rules.get_possible_matches(&c, &mut inner.possible_rules);
+            styles.get_possible_matches(&c, &mut inner.possible_rules);
         }
         if parent_dirty || props_dirty {
             parent_dirty = true;
@@ -639,49 +1051,82 @@ impl<E: Extension> Node<E> {
                 parent: Some(parent),
                 value: inner.value.as_chain(),
                 draw_rect: inner.draw_rect,
+                index,
+                child_count,
                 properties: &inner.properties,
+                inherited: &inner.inherited,
+                scoped_rules: inner.scoped_rules.as_ref(),
             };
             styles.used_keys.clear();
-            inner.uses_parent_size = false;
-            for rule in inner.possible_rules.iter().rev() {
-                if rule.test(&c) {
-                    inner.uses_parent_size |= rule.uses_parent_size;
-                    eval!(styles, c, rule.LAYOUT => val => {
-                        let new = val.convert::<String>();
-                        let new = new.as_ref().map(|v| v.as_str())
-                            .unwrap_or("absolute");
-                        if new != inner.layout.name() {
-                            if let Some(nl) = styles.layouts.get(new) {
-                                inner.layout = nl();
-                                inner.dirty_flags |= DirtyFlags::POSITION | DirtyFlags::SIZE | DirtyFlags::LAYOUT;
-                            }
-                        }
-                    });
-                    // TODO: Error/warn on incorrect types?
-                    eval!(styles, c, rule.SCROLL_X => val => {
-                        let new = val.convert().unwrap_or(0.0);
-                        if inner.scroll_position.0 != new {
-                            inner.scroll_position.0 = new;
-                            inner.dirty_flags |= DirtyFlags::SCROLL;
-                        }
-                    });
-                    eval!(styles, c, rule.SCROLL_Y => val => {
-                        let new = val.convert().unwrap_or(0.0);
-                        if inner.scroll_position.1 != new {
-                            inner.scroll_position.1 = new;
-                            inner.dirty_flags |= DirtyFlags::SCROLL;
+            inner.used_variables = UsedVariables::empty();
+            let matched: Vec<_> = inner.possible_rules.iter().rev()
+                .filter(|v| v.test(&c))
+                .map(|v| &**v)
+                .collect();
+            // Inherited properties that no rule sets are applied
+            // via a rule containing the parent's values
+            let inherited_rule = {
+                let values = parent.inherited.iter()
+                    .filter(|(k, _)| !matched.iter().any(|r| r.styles.contains_key(k)))
+                    .map(|(k, v)| (*k, Expr::Value(v.clone())))
+                    .collect::<FnvHashMap<_, _>>();
+                if values.is_empty() {
+                    None
+                } else {
+                    Some(Rule::new_inherited(values))
+                }
+            };
+            styles.inherited_values.get_mut().clear();
+            for rule in matched.into_iter().chain(inherited_rule.as_ref()) {
+                inner.used_variables |= rule.used_variables;
+                eval!(styles, c, rule.LAYOUT => val => {
+                    let new = val.convert::<String>();
+                    let new = new.as_ref().map(|v| v.as_str())
+                        .unwrap_or("absolute");
+                    if new != inner.layout.name() {
+                        if let Some(nl) = styles.layouts.get(new) {
+                            inner.layout = nl();
+                            inner.dirty_flags |= DirtyFlags::POSITION | DirtyFlags::SIZE | DirtyFlags::LAYOUT;
                         }
-                    });
-                    eval!(styles, c, rule.CLIP_OVERFLOW => val => {
-                        inner.clip_overflow = val.convert().unwrap_or(false);
-                    });
-                    inner.dirty_flags |= E::update_data(styles, &c, rule, &mut inner.ext);
-                    inner.dirty_flags |= inner.layout.update_data(styles, &c, rule);
-                    inner.dirty_flags |= parent_layout.update_child_data(styles, &c, rule, &mut inner.parent_data);
-
-                    styles.used_keys.extend(rule.styles.keys());
+                    }
+                });
+                eval!(styles, c, rule.SCROLL_X => val => {
+                    let new = val.convert().unwrap_or(0.0);
+                    if inner.scroll_position.0 != new {
+                        inner.scroll_position.0 = new;
+                        inner.dirty_flags |= DirtyFlags::SCROLL;
+                    }
+                });
+                eval!(styles, c, rule.SCROLL_Y => val => {
+                    let new = val.convert().unwrap_or(0.0);
+                    if inner.scroll_position.1 != new {
+                        inner.scroll_position.1 = new;
+                        inner.dirty_flags |= DirtyFlags::SCROLL;
+                    }
+                });
+                eval!(styles, c, rule.CLIP_OVERFLOW => val => {
+                    inner.clip_overflow = val.convert().unwrap_or(false);
+                });
+                inner.dirty_flags |= E::update_data(styles, &c, rule, &mut inner.ext);
+                inner.dirty_flags |= inner.layout.update_data(styles, &c, rule);
+                inner.dirty_flags |= parent_layout.update_child_data(styles, &c, rule, &mut inner.parent_data);
+
+                // Inherited keys that nothing evaluated on this node
+                // still need a value for its descendants
+                for (key, e) in rule.styles.iter() {
+                    if styles.inherited_keys.contains(key)
+                        && !styles.key_was_used(key)
+                        && !styles.inherited_values.get_mut().contains_key(key)
+                    {
+                        styles.eval_key(key, e, &c);
+                    }
                 }
+                styles.used_keys.extend(rule.styles.keys());
             }
+            inner.inherited = styles.inherited_values.get_mut()
+                .drain()
+                .filter_map(|(k, v)| v.map(|v| (k, v)))
+                .collect();
             if !styles.used_keys.contains(&CLIP_OVERFLOW) {
                 inner.clip_overflow = false;
             }
@@ -704,11 +1149,15 @@ impl<E: Extension> Node<E> {
             parent: Some(parent),
             value: inner.value.as_chain(),
             draw_rect: inner.draw_rect,
+            index,
+            child_count,
             properties: &inner.properties,
+            inherited: &inner.inherited,
+            scoped_rules: inner.scoped_rules.as_ref(),
         };
         if let NodeValue::Element(ref v) = inner.value {
-            for c in &v.children {
-                child_flags |= c.do_update(styles, &p, &mut *inner.layout, styles_updated, parent_dirty, inner.dirty_flags);
+            for (idx, c) in v.children.iter().enumerate() {
+                child_flags |= c.do_update(styles, &p, idx, &mut *inner.layout, styles_updated, parent_dirty, inner.dirty_flags);
             }
         }
         inner.dirty_flags |= inner.layout.check_child_flags(child_flags);
@@ -743,16 +1192,79 @@ impl<E: Extension> Node<E> {
         if inner.draw_rect != inner.prev_rect {
             for c in nodes {
                 let mut c = c.inner.borrow_mut();
-                if c.uses_parent_size {
+                if c.used_variables.contains(UsedVariables::PARENT_SIZE) {
                     c.properties_changed = true;
                     properties_changed = true;
                 }
             }
+            // Only updated on the next call to `layout` as rules
+            // using the node's own size to size it would never
+            // settle
+            if inner.used_variables.contains(UsedVariables::SELF_SIZE) {
+                inner.properties_changed = true;
+            }
         }
         inner.prev_rect = inner.draw_rect;
         properties_changed
     }
 
+    /// Marks this node and any child nodes that could be matched by
+    /// a rule with one of the passed keys as needing their rules
+    /// to be recomputed.
+    fn mark_styles_dirty(&self, keys: &FnvHashSet<RuleKey>) {
+        if keys.is_empty() {
+            return;
+        }
+        let inner: &mut _ = &mut *self.inner.borrow_mut();
+        let key = match inner.value {
+            NodeValue::Text(_) => RuleKeyBorrow::Text,
+            NodeValue::Element(ref e) => RuleKeyBorrow::ElementBorrow(&e.name),
+        };
+        if keys.contains(&key) {
+            inner.styles_dirty = true;
+        }
+        if let NodeValue::Element(ref v) = inner.value {
+            for c in &v.children {
+                c.mark_styles_dirty(keys);
+            }
+        }
+    }
+
+    /// Marks this node and any child nodes that use one of
+    /// the passed variables as changed.
+    fn mark_variables_changed(&self, vars: UsedVariables) {
+        let inner: &mut _ = &mut *self.inner.borrow_mut();
+        if inner.used_variables.intersects(vars) {
+            inner.properties_changed = true;
+        }
+        if let NodeValue::Element(ref v) = inner.value {
+            for c in &v.children {
+                c.mark_variables_changed(vars);
+            }
+        }
+    }
+
+    /// Marks nodes that depend on the number or order of
+    /// this element's children as changed.
+    ///
+    /// `first` is the index of the first child whose index
+    /// changed.
+    fn children_changed(inner: &mut NodeInner<E>, first: usize) {
+        if inner.used_variables.contains(UsedVariables::CHILD_COUNT) {
+            inner.properties_changed = true;
+        }
+        if let NodeValue::Element(ref v) = inner.value {
+            for (idx, c) in v.children.iter().enumerate() {
+                let mut c = c.inner.borrow_mut();
+                if c.used_variables.contains(UsedVariables::SIBLING_COUNT)
+                    || (idx >= first && c.used_variables.contains(UsedVariables::INDEX))
+                {
+                    c.properties_changed = true;
+                }
+            }
+        }
+    }
+
     fn render<V>(&self, visitor: &mut V)
     where
         V: RenderVisitor<E>,
@@ -818,17 +1330,19 @@ impl<E: Extension> Node<E> {
         if node.inner.borrow().parent.is_some() {
             return false;
         }
-        if let NodeValue::Element(ref mut e) = self.inner.borrow_mut().value {
+        let inner: &mut NodeInner<_> = &mut *self.inner.borrow_mut();
+        if let NodeValue::Element(ref mut e) = inner.value {
             {
                 let mut inner = node.inner.borrow_mut();
                 inner.parent = Some(Rc::downgrade(&self.inner));
                 inner.rules_dirty = true;
             }
             e.children.insert(0, node);
-            true
         } else {
-            false
+            return false;
         }
+        Node::children_changed(inner, 0);
+        true
     }
 
     /// Adds the passed node as a child to this node.
@@ -838,17 +1352,20 @@ impl<E: Extension> Node<E> {
         if node.inner.borrow().parent.is_some() {
             return false;
         }
-        if let NodeValue::Element(ref mut e) = self.inner.borrow_mut().value {
+        let inner: &mut NodeInner<_> = &mut *self.inner.borrow_mut();
+        let first = if let NodeValue::Element(ref mut e) = inner.value {
             {
                 let mut inner = node.inner.borrow_mut();
                 inner.parent = Some(Rc::downgrade(&self.inner));
                 inner.rules_dirty = true;
             }
             e.children.push(node);
-            true
+            e.children.len() - 1
         } else {
-            false
-        }
+            return false;
+        };
+        Node::children_changed(inner, first);
+        true
     }
 
     /// Removes the passed node as a child from this node.
@@ -864,17 +1381,22 @@ impl<E: Extension> Node<E> {
             return false;
         }
         let inner: &mut NodeInner<_> = &mut *self.inner.borrow_mut();
-        if let NodeValue::Element(ref mut e) = inner.value {
+        let first = if let NodeValue::Element(ref mut e) = inner.value {
+            let first = e.children.iter()
+                .position(|v| Rc::ptr_eq(&v.inner, &node.inner))
+                .unwrap_or(0);
             e.children.retain(|v| !Rc::ptr_eq(&v.inner, &node.inner));
             {
                 let mut inner = node.inner.borrow_mut();
                 inner.parent = None;
                 inner.rules_dirty = true;
             }
-            true
+            first
         } else {
-            false
-        }
+            return false;
+        };
+        Node::children_changed(inner, first);
+        true
     }
 
     /// Returns a vector containing the child nodes of this
@@ -1066,54 +1588,166 @@ impl<E: Extension> Node<E> {
 
     /// Creates a node from a string
     pub fn from_str(s: &str) -> Result<Node<E>, syntax::PError> {
-        syntax::desc::Document::parse(s).map(|v| Node::from_document(v))
+        Node::from_str_with_data(s, &DataContext::new())
+    }
+
+    /// Creates a node from a string using the data context
+    /// for `@if`, `@for` and `$name`
+    pub fn from_str_with_data<'a>(s: &'a str, data: &DataContext<E>) -> Result<Node<E>, syntax::PError<'a>> {
+        syntax::desc::Document::parse(s).and_then(|v| Node::from_document_with_data(v, data))
+    }
+
+    /// Creates a list of nodes from a string that may contain
+    /// multiple top level nodes
+    pub fn fragment_from_str<'a>(s: &'a str) -> Result<Vec<Node<E>>, syntax::PError<'a>> {
+        Node::fragment_from_str_with_data(s, &DataContext::new())
+    }
+
+    /// Creates a list of nodes from a string using the data
+    /// context for `@if`, `@for` and `$name`
+    pub fn fragment_from_str_with_data<'a>(s: &'a str, data: &DataContext<E>) -> Result<Vec<Node<E>>, syntax::PError<'a>> {
+        syntax::desc::Document::parse(s).and_then(|v| Node::fragment_from_document_with_data(v, data))
     }
 
     /// Creates a node from a parsed document.
-    pub fn from_document(desc: syntax::desc::Document) -> Node<E> {
-        Node::from_doc_element(desc.root)
+    ///
+    /// Any templates defined in the document are expanded
+    /// where they are used. Fails if the document doesn't have
+    /// exactly one top level node or imports other files
+    /// (see [`from_file`](#method.from_file)).
+    pub fn from_document(desc: syntax::desc::Document) -> Result<Node<E>, syntax::PError> {
+        Node::from_document_with_data(desc, &DataContext::new())
     }
 
-    fn from_doc_text(
-        desc: &str,
-        properties: FnvHashMap<syntax::Ident, syntax::desc::ValueType>,
-    ) -> Node<E> {
-        let text = unescape(desc);
-        Node {
-            inner: Rc::new(RefCell::new(NodeInner {
-                value: NodeValue::Text(text),
-                properties: properties
-                    .into_iter()
-                    .map(|(n, v)| (n.name.into(), Value::from(v)))
-                    .collect(),
-                .. Default::default()
-            })),
+    /// Creates a node from a parsed document using the data
+    /// context for `@if`, `@for` and `$name`.
+    ///
+    /// See [`from_document`](#method.from_document)
+    pub fn from_document_with_data<'a>(desc: syntax::desc::Document<'a>, data: &DataContext<E>) -> Result<Node<E>, syntax::PError<'a>> {
+        if let Some(extra) = desc.nodes.get(1) {
+            return Err(syntax::Errors::new(
+                extra.position().into(),
+                syntax::Error::Message(syntax::Info::Borrowed("Expected a single root node")),
+            ));
+        }
+        let position = desc.nodes.first()
+            .map_or(syntax::Position { line_number: 1, column: 1 }, |v| v.position());
+        let mut nodes = Node::fragment_from_document_with_data(desc, data)?;
+        // `@if`, `@for` and templates can expand to any number of nodes
+        match nodes.len() {
+            1 => Ok(nodes.remove(0)),
+            0 => Err(syntax::Errors::new(
+                position.into(),
+                syntax::Error::Message(syntax::Info::Borrowed("Missing root element")),
+            )),
+            _ => Err(syntax::Errors::new(
+                position.into(),
+                syntax::Error::Message(syntax::Info::Borrowed("Expected a single root node")),
+            )),
         }
     }
 
-    fn from_doc_element(desc: syntax::desc::Element) -> Node<E> {
-        let node = Node {
-            inner: Rc::new(RefCell::new(NodeInner {
-                value: NodeValue::Element(Element {
-                    name: desc.name.name.into(),
-                    children: Vec::with_capacity(desc.nodes.len()),
-                }),
-                properties: desc.properties
-                    .into_iter()
-                    .map(|(n, v)| (n.name.into(), Value::from(v)))
-                    .collect(),
-                .. Default::default()
-            })),
-        };
-
-        for c in desc.nodes.into_iter().map(|n| match n {
-            syntax::desc::Node::Element(e) => Node::from_doc_element(e),
-            syntax::desc::Node::Text(t, _, props) => Node::from_doc_text(t, props),
-        }) {
-            node.add_child(c);
+    /// Creates a node for every top level node in a parsed
+    /// document.
+    ///
+    /// Fails if the document imports other files.
+    pub fn fragment_from_document(desc: syntax::desc::Document) -> Result<Vec<Node<E>>, syntax::PError> {
+        Node::fragment_from_document_with_data(desc, &DataContext::new())
+    }
+
+    /// Creates a node for every top level node in a parsed
+    /// document using the data context for `@if`, `@for`
+    /// and `$name`.
+    pub fn fragment_from_document_with_data<'a>(desc: syntax::desc::Document<'a>, data: &DataContext<E>) -> Result<Vec<Node<E>>, syntax::PError<'a>> {
+        if let Some(import) = desc.imports.first() {
+            return Err(syntax::Errors::new(
+                import.position.into(),
+                syntax::Error::Message(syntax::Info::Borrowed("Imports require a resource resolver")),
+            ));
         }
+        desc::check_literals::<E>(&desc)?;
+        desc::Builder::new(&desc.templates).nodes(&desc.nodes, data)
+    }
+
+    /// Creates a node from the file at the given path.
+    ///
+    /// Templates can be imported from other files via `@import`.
+    /// Files are loaded via the passed resolver and imported files
+    /// can't contain any nodes.
+    pub fn from_file<R>(path: &str, resolver: &mut R) -> Result<Node<E>, FileError>
+        where R: ResourceResolver + ?Sized
+    {
+        Node::from_file_with_data(path, resolver, &DataContext::new())
+    }
+
+    /// Creates a node from the file at the given path using
+    /// the data context for `@if`, `@for` and `$name`.
+    ///
+    /// See [`from_file`](#method.from_file)
+    pub fn from_file_with_data<R>(path: &str, resolver: &mut R, data: &DataContext<E>) -> Result<Node<E>, FileError>
+        where R: ResourceResolver + ?Sized
+    {
+        Node::nodes_from_file(path, resolver, data, true)?
+            .pop()
+            .ok_or_else(|| FileError::new(path, "Missing root element"))
+    }
+
+    /// Creates a node for every top level node in the file
+    /// at the given path.
+    ///
+    /// See [`from_file`](#method.from_file)
+    pub fn fragment_from_file<R>(path: &str, resolver: &mut R) -> Result<Vec<Node<E>>, FileError>
+        where R: ResourceResolver + ?Sized
+    {
+        Node::nodes_from_file(path, resolver, &DataContext::new(), false)
+    }
+
+    /// Creates a node for every top level node in the file
+    /// at the given path using the data context for `@if`,
+    /// `@for` and `$name`.
+    pub fn fragment_from_file_with_data<R>(path: &str, resolver: &mut R, data: &DataContext<E>) -> Result<Vec<Node<E>>, FileError>
+        where R: ResourceResolver + ?Sized
+    {
+        Node::nodes_from_file(path, resolver, data, false)
+    }
 
-        node
+    fn nodes_from_file<R>(path: &str, resolver: &mut R, data: &DataContext<E>, single: bool) -> Result<Vec<Node<E>>, FileError>
+        where R: ResourceResolver + ?Sized
+    {
+        let sources: Vec<resource::Source<syntax::desc::Document<'static>>> = resource::load_sources(resolver, path)?;
+        for source in &sources {
+            desc::check_literals::<E>(&source.doc)
+                .map_err(|err| FileError::from_parse_error(&*source.name, err))?;
+        }
+        let (doc, imported) = sources.split_last().expect("Missing document");
+        let doc = &doc.doc;
+        for source in imported {
+            if let Some(node) = source.doc.nodes.first() {
+                return Err(FileError {
+                    position: Some(node.position()),
+                    .. FileError::new(&*source.name, "Imported documents can't contain nodes")
+                });
+            }
+        }
+        if single {
+            if let Some(extra) = doc.nodes.get(1) {
+                return Err(FileError {
+                    position: Some(extra.position()),
+                    .. FileError::new(path, "Expected a single root node")
+                });
+            }
+        }
+        let templates = sources.iter().flat_map(|v| v.doc.templates.iter());
+        let nodes = desc::Builder::new(templates).nodes(&doc.nodes, data)
+            .map_err(|err| FileError::from_parse_error(path, err))?;
+        // `@if`, `@for` and templates can expand to any number of nodes
+        if single && nodes.len() > 1 {
+            return Err(FileError {
+                position: doc.nodes.first().map(|v| v.position()),
+                .. FileError::new(path, "Expected a single root node")
+            });
+        }
+        Ok(nodes)
     }
 
     fn root() -> Node<E> {
@@ -1129,24 +1763,158 @@ impl<E: Extension> Node<E> {
     }
 }
 
+/// Prints the node and its children in the description
+/// format.
+///
+/// Properties are sorted by name. Properties that can't be
+/// described (extension values, non-finite floats and keys
+/// or atoms that aren't identifiers) are skipped.
+impl <E> fmt::Display for Node<E>
+    where E: Extension
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_desc(f, 0)
+    }
+}
+
+impl <E> Node<E>
+    where E: Extension
+{
+    fn write_desc(&self, f: &mut fmt::Formatter, indent: usize) -> fmt::Result {
+        for _ in 0 .. indent {
+            f.write_str("    ")?;
+        }
+        let inner = self.inner.borrow();
+        match inner.value {
+            NodeValue::Element(ref e) => f.write_str(&e.name)?,
+            NodeValue::Text(ref t) => write!(f, "\"{}\"", escape(t))?,
+        }
+
+        let mut props: Vec<_> = inner.properties.iter()
+            .filter(|v| is_ident(v.0) && printable(v.1))
+            .collect();
+        props.sort_by_key(|v| v.0);
+        if !props.is_empty() {
+            f.write_str("(")?;
+            for (idx, (key, val)) in props.into_iter().enumerate() {
+                if idx != 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "{}=", key)?;
+                write_desc_value(f, val)?;
+            }
+            f.write_str(")")?;
+        }
+
+        if let NodeValue::Element(ref e) = inner.value {
+            if !e.children.is_empty() {
+                f.write_str(" {\n")?;
+                for c in &e.children {
+                    c.write_desc(f, indent + 1)?;
+                }
+                for _ in 0 .. indent {
+                    f.write_str("    ")?;
+                }
+                f.write_str("}")?;
+            }
+        }
+        f.write_str("\n")
+    }
+}
+
+/// Returns whether the value can be written in the
+/// description format
+fn printable<E: Extension>(v: &Value<E>) -> bool {
+    match *v {
+        Value::ExtValue(_) => false,
+        Value::Float(v) => v.is_finite(),
+        Value::Atom(ref v) => is_ident(v),
+        Value::List(ref v) => v.iter().all(printable),
+        _ => true,
+    }
+}
+
+/// Returns whether the string would parse back as a single
+/// identifier instead of a number or boolean
+fn is_ident(v: &str) -> bool {
+    match v.chars().next() {
+        Some(c) if !c.is_numeric() => {},
+        _ => return false,
+    }
+    v != "true" && v != "false"
+        && v.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn write_desc_value<E: Extension>(f: &mut fmt::Formatter, v: &Value<E>) -> fmt::Result {
+    match *v {
+        Value::Boolean(v) => write!(f, "{}", v),
+        Value::Integer(v) => write!(f, "{}", v),
+        Value::Float(v) => {
+            let v = v.to_string();
+            f.write_str(&v)?;
+            if !v.contains('.') {
+                f.write_str(".0")?;
+            }
+            Ok(())
+        },
+        Value::String(ref v) => write!(f, "\"{}\"", escape(v)),
+        Value::Color(v) => write!(f, "{}", v),
+        Value::Atom(ref v) => f.write_str(v),
+        Value::List(ref v) => {
+            f.write_str("[")?;
+            for (idx, v) in v.iter().enumerate() {
+                if idx != 0 {
+                    f.write_str(", ")?;
+                }
+                write_desc_value(f, v)?;
+            }
+            f.write_str("]")
+        },
+        Value::ExtValue(_) => unreachable!(),
+    }
+}
+
+/// The inverse of `unescape`
+fn escape(v: &str) -> String {
+    let mut text = String::with_capacity(v.len());
+    for c in v.chars() {
+        match c {
+            '"' => text.push_str("\\\""),
+            '\\' => text.push_str("\\\\"),
+            '\t' => text.push_str("\\t"),
+            '\n' => text.push_str("\\n"),
+            '\r' => text.push_str("\\r"),
+            _ => text.push(c),
+        }
+    }
+    text
+}
+
 fn unescape(v: &str) -> String {
     let mut text = String::new();
-    let mut special = false;
-    for c in v.chars() {
-        if special {
-            match c {
-                't' => text.push('\t'),
-                'n' => text.push('\n'),
-                'r' => text.push('\r'),
-                _ => text.push(c),
-            }
-            special = false;
+    let mut chars = v.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
             continue;
         }
-        if c == '\\' {
-            special = true;
-        } else {
-            text.push(c);
+        match chars.next() {
+            Some('t') => text.push('\t'),
+            Some('n') => text.push('\n'),
+            Some('r') => text.push('\r'),
+            // `\u{..}`, already validated by the parser. A
+            // bare `\u` is kept as `u` like other escapes
+            Some('u') if chars.as_str().starts_with('{') => {
+                let code: String = chars.by_ref()
+                    .skip(1)
+                    .take_while(|c| *c != '}')
+                    .collect();
+                if let Some(c) = u32::from_str_radix(&code, 16).ok().and_then(std::char::from_u32) {
+                    text.push(c);
+                }
+            },
+            Some(c) => text.push(c),
+            None => {},
         }
     }
     text
@@ -1185,6 +1953,12 @@ pub struct NodeInner<E: Extension> {
     done_layout: bool,
     // Set when added/removed from a node
     rules_dirty: bool,
+    // Set when the loaded styles that could match this node change
+    styles_dirty: bool,
+    // Styles that only apply to this node and its children
+    scoped_rules: Option<Rules<E>>,
+    // The computed values of inherited style keys
+    inherited: FnvHashMap<StaticKey, Value<E>>,
     dirty_flags: DirtyFlags,
     /// The value of the node.
     ///
@@ -1198,7 +1972,7 @@ pub struct NodeInner<E: Extension> {
     pub text_changed: bool,
     layout: Box<dyn BoxLayoutEngine<E>>,
     parent_data: Box<dyn Any>,
-    uses_parent_size: bool,
+    used_variables: UsedVariables,
     prev_rect: Rect,
     /// The current draw position of this node
     pub draw_rect: Rect,
@@ -1228,9 +2002,12 @@ impl <E> Default for NodeInner<E>
             possible_rules: Vec::new(),
             done_layout: false,
             rules_dirty: true,
+            styles_dirty: false,
+            scoped_rules: None,
+            inherited: FnvHashMap::default(),
             text_changed: false,
             dirty_flags: DirtyFlags::empty(),
-            uses_parent_size: false,
+            used_variables: UsedVariables::empty(),
             prev_rect: Rect{x: 0, y: 0, width: 0, height: 0},
             draw_rect: Rect{x: 0, y: 0, width: 0, height: 0},
             scroll_position: (0.0, 0.0),
@@ -1323,7 +2100,11 @@ pub struct NodeChain<'a, E: Extension + 'a> {
     parent: Option<&'a NodeChain<'a, E>>,
     value: NCValue<'a>,
     draw_rect: Rect,
+    index: usize,
+    child_count: usize,
     properties: &'a FnvHashMap<String, Value<E>>,
+    inherited: &'a FnvHashMap<StaticKey, Value<E>>,
+    scoped_rules: Option<&'a Rules<E>>,
 }
 
 impl <'a, E> NodeChain<'a, E>
@@ -1363,6 +2144,12 @@ pub enum Value<E: Extension> {
     Float(f64),
     /// A string value
     String(String),
+    /// A colour value
+    Color(Color),
+    /// A bare identifier (e.g. `center`)
+    Atom(String),
+    /// A list of values
+    List(Vec<Value<E>>),
     /// An extension defined value
     ExtValue(E::Value),
 }
@@ -1385,6 +2172,15 @@ impl <E> Value<E>
     {
         V::from_value_ref(self)
     }
+
+    /// Converts a value parsed from a description document.
+    ///
+    /// Fails if the value is a template parameter as it has
+    /// no value outside of a template or if a tagged literal
+    /// fails to parse.
+    pub fn from_desc<'a>(v: &syntax::desc::ValueType<'a>) -> Result<Value<E>, syntax::PError<'a>> {
+        desc::convert_value(v)
+    }
 }
 
 impl <E> Clone for Value<E>
@@ -1396,6 +2192,9 @@ impl <E> Clone for Value<E>
             Value::Integer(v) => Value::Integer(v),
             Value::Float(v) => Value::Float(v),
             Value::String(ref v) => Value::String(v.clone()),
+            Value::Color(v) => Value::Color(v),
+            Value::Atom(ref v) => Value::Atom(v.clone()),
+            Value::List(ref v) => Value::List(v.clone()),
             Value::ExtValue(ref v) => Value::ExtValue(v.clone()),
         }
     }
@@ -1411,25 +2210,15 @@ impl <E> PartialEq for Value<E>
             (&Integer(a), &Integer(b)) => a == b,
             (&Float(a), &Float(b)) => a == b,
             (&String(ref a), &String(ref b)) => a == b,
+            (&Color(a), &Color(b)) => a == b,
+            (Atom(a), Atom(b)) => a == b,
+            (List(a), List(b)) => a == b,
             (&ExtValue(ref a), &ExtValue(ref b)) => a == b,
             _ => false,
         }
     }
 }
 
-impl <'a, E> From<syntax::desc::ValueType<'a>> for Value<E>
-    where E: Extension
-{
-    fn from(v: syntax::desc::ValueType<'a>) -> Value<E> {
-        match v.value {
-            syntax::desc::Value::Boolean(val) => Value::Boolean(val),
-            syntax::desc::Value::Integer(val) => Value::Integer(val),
-            syntax::desc::Value::Float(val) => Value::Float(val),
-            syntax::desc::Value::String(val) => Value::String(unescape(val)),
-        }
-    }
-}
-
 /// Types that can be converted to and from a value
 pub trait ConvertValue<E: Extension>: Sized {
     /// The reference type of this value.
@@ -1534,19 +2323,42 @@ impl <E> ConvertValue<E> for bool
     }
 }
 
+impl <E> ConvertValue<E> for Color
+    where E: Extension
+{
+    type RefType = Color;
+    fn from_value(v: Value<E>) -> Option<Color> {
+        match v {
+            Value::Color(c) => Some(c),
+            _ => None,
+        }
+    }
+    fn from_value_ref(v: &Value<E>) -> Option<&Self::RefType> {
+        match v {
+            Value::Color(c) => Some(c),
+            _ => None,
+        }
+    }
+    fn to_value(v: Self) -> Value<E> {
+        Value::Color(v)
+    }
+}
+
+/// Atoms are also converted so that properties can use
+/// either `"rows"` or `rows`
 impl <E> ConvertValue<E> for String
     where E: Extension
 {
     type RefType = str;
     fn from_value(v: Value<E>) -> Option<String> {
         match v {
-            Value::String(s) => Some(s.clone()),
+            Value::String(s) | Value::Atom(s) => Some(s),
             _ => None,
         }
     }
     fn from_value_ref(v: &Value<E>) -> Option<&Self::RefType> {
         match v {
-            Value::String(s) => Some(s.as_str()),
+            Value::String(s) | Value::Atom(s) => Some(s.as_str()),
             _ => None,
         }
     }
@@ -1554,6 +2366,33 @@ impl <E> ConvertValue<E> for String
         Value::String(v)
     }
 }
+/// Fails if any of the items fail to convert
+impl <E, T> ConvertValue<E> for Vec<T>
+    where E: Extension,
+          T: ConvertValue<E>,
+{
+    type RefType = [Value<E>];
+    fn from_value(v: Value<E>) -> Option<Vec<T>> {
+        match v {
+            Value::List(l) => l.into_iter()
+                .map(T::from_value)
+                .collect(),
+            _ => None,
+        }
+    }
+    fn from_value_ref(v: &Value<E>) -> Option<&Self::RefType> {
+        match v {
+            Value::List(l) => Some(l.as_slice()),
+            _ => None,
+        }
+    }
+    fn to_value(v: Self) -> Value<E> {
+        Value::List(v.into_iter()
+            .map(T::to_value)
+            .collect())
+    }
+}
+
 impl <E> ConvertValue<E> for Value<E>
     where E: Extension
 {
@@ -35,6 +35,7 @@ pub enum ValueRef<'a, E: Extension + 'a> {
     Integer(i32),
     Float(f64),
     String(Cow<'a, str>),
+    Color(Color),
     ExtValue(&'a E::Value),
 }
 
@@ -47,6 +48,7 @@ impl <'a, E> Clone for ValueRef<'a, E>
             ValueRef::Integer(v) => ValueRef::Integer(*v),
             ValueRef::Float(v) => ValueRef::Float(*v),
             ValueRef::String(v) => ValueRef::String(v.clone()),
+            ValueRef::Color(v) => ValueRef::Color(*v),
             ValueRef::ExtValue(v) => ValueRef::ExtValue(*v),
         }
     }
@@ -107,6 +109,14 @@ impl <'a, E> AsValueRef<'a, E> for bool
     }
 }
 
+impl <'a, E> AsValueRef<'a, E> for Color
+    where E: Extension
+{
+    fn as_value_ref(self) -> ValueRef<'a, E> {
+        ValueRef::Color(self)
+    }
+}
+
 impl<'a, E> Query<'a, E>
     where E: Extension + 'a
 {
@@ -351,6 +361,7 @@ impl<'a, E> Iterator for QueryIterator<'a, E>
                             (Some(Value::Float(a)), ValueRef::Float(b)) => a == b,
                             (Some(Value::Boolean(a)), ValueRef::Boolean(b)) => a == b,
                             (Some(Value::String(a)), ValueRef::String(b)) => a == b,
+                            (Some(Value::Color(a)), ValueRef::Color(b)) => a == b,
                             (Some(Value::ExtValue(a)), ValueRef::ExtValue(b)) => a == *b,
                             _ => false,
                         };
@@ -386,7 +397,7 @@ panel {
 
 "#,
     ).unwrap();
-    let node = Node::<tests::TestExt>::from_document(doc);
+    let node = Node::<tests::TestExt>::from_document(doc).unwrap();
 
     for n in node.query()
         .name("panel")
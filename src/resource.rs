@@ -0,0 +1,149 @@
+
+use super::*;
+use syntax::{FileError, Import, Position};
+
+/// Loads the files referenced by `@import` in style and
+/// description documents.
+///
+/// Imports are relative to the directory of the file that
+/// contains them (`@import "common.style"` in `ui/menu.style`
+/// loads `ui/common.style`) unless they start with `/`. Paths
+/// use `/` as the separator and `.`/`..` are resolved before
+/// being passed to the resolver.
+///
+/// This is implemented for closures of the form
+/// `FnMut(&str) -> Result<String, String>` so files can
+/// be read from anywhere (e.g. the filesystem or a packed
+/// archive).
+pub trait ResourceResolver {
+    /// Returns the contents of the file at the path or a
+    /// message describing why it couldn't be loaded.
+    fn load(&mut self, path: &str) -> Result<String, String>;
+}
+
+impl <F> ResourceResolver for F
+    where F: FnMut(&str) -> Result<String, String>
+{
+    fn load(&mut self, path: &str) -> Result<String, String> {
+        self(path)
+    }
+}
+
+/// A document type that can import other files
+pub(crate) trait ImportDocument: Sized {
+    /// Parses the document into one that doesn't borrow
+    /// the source
+    fn parse<'a>(source: &'a str) -> Result<Self, syntax::PError<'a>>;
+
+    fn imports(&self) -> &[Import<'static>];
+}
+
+impl ImportDocument for syntax::style::Document<'static> {
+    fn parse<'a>(source: &'a str) -> Result<Self, syntax::PError<'a>> {
+        syntax::style::Document::parse(source).map(|v| v.into_owned())
+    }
+
+    fn imports(&self) -> &[Import<'static>] {
+        &self.imports
+    }
+}
+
+impl ImportDocument for syntax::desc::Document<'static> {
+    fn parse<'a>(source: &'a str) -> Result<Self, syntax::PError<'a>> {
+        syntax::desc::Document::parse(source).map(|v| v.into_owned())
+    }
+
+    fn imports(&self) -> &[Import<'static>] {
+        &self.imports
+    }
+}
+
+/// A file loaded via a `ResourceResolver`
+pub(crate) struct Source<D> {
+    pub(crate) name: String,
+    pub(crate) doc: D,
+}
+
+/// Loads and parses the file at the path and all the files
+/// it imports.
+///
+/// Imported files are returned before the files that import
+/// them and each file is only returned once.
+pub(crate) fn load_sources<R, D>(resolver: &mut R, path: &str) -> Result<Vec<Source<D>>, FileError>
+    where R: ResourceResolver + ?Sized,
+          D: ImportDocument,
+{
+    let source = resolver.load(path)
+        .map_err(|err| FileError::new(path, err))?;
+    let mut out = Vec::new();
+    load_imports(resolver, path, &source, &mut Vec::new(), &mut out)?;
+    Ok(out)
+}
+
+fn load_imports<R, D>(
+    resolver: &mut R,
+    path: &str, source: &str,
+    stack: &mut Vec<String>, out: &mut Vec<Source<D>>,
+) -> Result<(), FileError>
+    where R: ResourceResolver + ?Sized,
+          D: ImportDocument,
+{
+    let doc = D::parse(source)
+        .map_err(|err| FileError::from_parse_error(path, err))?;
+    let found: Vec<_> = doc.imports().iter()
+        .map(|v| (resolve_path(path, &unescape(&v.path)), v.position))
+        .collect();
+
+    stack.push(path.into());
+    for (import, position) in found {
+        if stack.contains(&import) {
+            let mut cycle = stack.join(" -> ");
+            cycle.push_str(" -> ");
+            cycle.push_str(&import);
+            return Err(error_at(path, position, format!("Import cycle: {}", cycle)));
+        }
+        if out.iter().any(|v| v.name == import) {
+            continue;
+        }
+        let source = resolver.load(&import)
+            .map_err(|err| error_at(path, position, format!("Failed to load {:?}: {}", import, err)))?;
+        load_imports(resolver, &import, &source, stack, out)?;
+    }
+    stack.pop();
+
+    out.push(Source {
+        name: path.into(),
+        doc,
+    });
+    Ok(())
+}
+
+/// Resolves an import against the path of the file
+/// containing it
+fn resolve_path(importer: &str, import: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    if !import.starts_with('/') {
+        if let Some(idx) = importer.rfind('/') {
+            parts.extend(importer[..idx].split('/'));
+        }
+    }
+    for part in import.split('/') {
+        match part {
+            "" | "." => {},
+            ".." if matches!(parts.last(), Some(v) if !v.is_empty() && *v != "..") => { parts.pop(); },
+            _ => parts.push(part),
+        }
+    }
+    let mut path = parts.join("/");
+    if import.starts_with('/') {
+        path.insert(0, '/');
+    }
+    path
+}
+
+fn error_at(file: &str, position: Position, message: String) -> FileError {
+    FileError {
+        position: Some(position),
+        .. FileError::new(file, message)
+    }
+}
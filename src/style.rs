@@ -3,19 +3,47 @@ use super::*;
 use std::hash::{Hash, Hasher};
 
 pub(crate) type SFunc<E> = Box<for<'a> Fn(&mut (Iterator<Item=Result<Value<E>, Error<'a>>> + 'a)) -> Result<Value<E>, Error<'a>> + 'static>;
+pub(crate) type PropertyMatchers<E> = Vec<(String, ValueMatcher<E>)>;
+type PropertyReplacer = FnvHashMap<String, (usize, String)>;
+type RuleStyles<E> = Rc<FnvHashMap<StaticKey, Expr<E>>>;
+pub(crate) type StyleSource = resource::Source<syntax::style::Document<'static>>;
+
+/// The body of a rule shared between each selector in a
+/// selector list. The styles are compiled once for each
+/// distinct set of variables bound by the selectors.
+struct RuleBody<'a, E: Extension> {
+    source: FnvHashMap<syntax::Ident<'a>, syntax::style::ExprType<'a>>,
+    compiled: Vec<(PropertyReplacer, RuleStyles<E>, UsedVariables)>,
+}
 
 /// Stores rules, functions and layouts needed for computing styles
 pub struct Styles<E: Extension> {
     pub(crate) _ext: ::std::marker::PhantomData<E>,
     pub(crate) static_keys: FnvHashMap<&'static str, StaticKey>,
-    pub(crate) rules: Rules<E>,
+    // Sorted by priority, lowest first
+    pub(crate) layers: Vec<StyleLayer<E>>,
+    pub(crate) inherited_keys: FnvHashSet<StaticKey>,
+    pub(crate) screen_size: (i32, i32),
     pub(crate) funcs: FnvHashMap<StaticKey, SFunc<E>>,
     pub(crate) layouts: FnvHashMap<&'static str, Box<Fn() -> Box<BoxLayoutEngine<E>>>>,
+    // The atoms allowed for each key
+    pub(crate) atoms: FnvHashMap<StaticKey, FnvHashSet<&'static str>>,
+    // The types declared for each key. Keys without an
+    // entry accept any value
+    pub(crate) key_types: FnvHashMap<StaticKey, ValueKind>,
+    pub(crate) errors: RefCell<Vec<EvalError>>,
+    // The results of evaluating inherited keys on the node
+    // currently being updated
+    pub(crate) inherited_values: RefCell<FnvHashMap<StaticKey, Option<Value<E>>>>,
     pub(crate) next_rule_id: u32,
+    pub(crate) next_layer_id: u32,
     // Stored here for reuse to save on allocations
     pub(crate) used_keys: FnvHashSet<StaticKey>,
 }
 
+/// The most errors kept between calls to `Manager::take_errors`
+const MAX_EVAL_ERRORS: usize = 256;
+
 impl <E: Extension> Styles<E> {
     #[inline]
     #[doc(hidden)]
@@ -23,14 +51,307 @@ impl <E: Extension> Styles<E> {
         self.used_keys.contains(key)
     }
 
-    pub(crate) fn load_styles<'a>(&mut self, name: &str, doc: syntax::style::Document<'a>) -> Result<(), syntax::PError<'a>>{
-        for rule in doc.rules {
+    /// Evaluates the expression set for the key. Errors are
+    /// collected instead of being returned, as are values of
+    /// the wrong type (which are still returned).
+    #[doc(hidden)]
+    // Used by the `eval!` macro
+    pub fn eval_key(&self, key: &StaticKey, e: &Expr<E>, node: &NodeChain<E>) -> Option<Value<E>> {
+        let val = match e.eval(self, node) {
+            Ok(val) => {
+                if let Some(expected) = self.key_types.get(key) {
+                    if !expected.contains(ValueKind::of(&val)) {
+                        self.report_error(key, e, Error::IncorrectType {
+                            expected: *expected,
+                            found: get_ty(&val),
+                        });
+                    }
+                }
+                Some(val)
+            },
+            Err(err) => {
+                self.report_error(key, e, err);
+                None
+            },
+        };
+        // Stored so that descendants inherit the value that
+        // was applied to this node
+        if self.inherited_keys.contains(key) {
+            self.inherited_values.borrow_mut().insert(*key, val.clone());
+        }
+        val
+    }
+
+    fn report_error(&self, key: &StaticKey, e: &Expr<E>, err: Error) {
+        let mut errors = self.errors.borrow_mut();
+        // Restyling many nodes with the same rule would otherwise
+        // report the same error for each of them
+        if errors.len() >= MAX_EVAL_ERRORS {
+            return;
+        }
+        let err = EvalError {
+            key: *key,
+            expr: e.to_string(),
+            error: err.into_owned(),
+        };
+        if !errors.contains(&err) {
+            errors.push(err);
+        }
+    }
+
+    /// Loads the document into the named layer, creating the
+    /// layer if it doesn't exist.
+    ///
+    /// Rules loaded before an error are kept. The keys of nodes
+    /// that may be affected by the new rules are added to `dirty`.
+    pub(crate) fn load_styles<'a>(&mut self, name: &str, doc: syntax::style::Document<'a>, dirty: &mut FnvHashSet<RuleKey>) -> Result<(), syntax::PError<'a>>{
+        let mut rules = Rules::new();
+        let ret = self.load_rules(&mut rules, doc);
+        self.add_layer_rules(name, rules, dirty);
+        ret
+    }
+
+    /// Loads the files into the named layer.
+    ///
+    /// Mixins defined in any of the files can be used by the others.
+    /// See [`load_styles`](#method.load_styles)
+    pub(crate) fn load_styles_sources(&mut self, name: &str, sources: Vec<StyleSource>, dirty: &mut FnvHashSet<RuleKey>) -> Result<(), syntax::FileError> {
+        let mut rules = Rules::new();
+        let ret = self.load_rules_sources(&mut rules, sources);
+        self.add_layer_rules(name, rules, dirty);
+        ret
+    }
+
+    fn add_layer_rules(&mut self, name: &str, rules: Rules<E>, dirty: &mut FnvHashSet<RuleKey>) {
+        dirty.extend(rules.root_keys());
+        let idx = self.layer_index(name);
+        self.layers[idx].rules.merge(rules);
+    }
+
+    pub(crate) fn load_rules<'a>(&mut self, rules: &mut Rules<E>, doc: syntax::style::Document<'a>) -> Result<(), syntax::PError<'a>>{
+        if let Some(import) = doc.imports.first() {
+            return Err(syntax::Errors::new(
+                import.position.into(),
+                syntax::Error::Message(syntax::Info::Borrowed("Imports require a resource resolver")),
+            ));
+        }
+        let mixins: FnvHashMap<_, _> = doc.mixins.iter()
+            .map(|v| (&*v.name.name, v))
+            .collect();
+        self.add_rules(rules, doc.rules, &mixins)
+    }
+
+    pub(crate) fn load_rules_sources(&mut self, rules: &mut Rules<E>, sources: Vec<StyleSource>) -> Result<(), syntax::FileError> {
+        let mut doc_rules = Vec::with_capacity(sources.len());
+        let mut doc_mixins = Vec::with_capacity(sources.len());
+        for source in sources {
+            doc_rules.push((source.name, source.doc.rules));
+            doc_mixins.push(source.doc.mixins);
+        }
+        // Later files take precedence
+        let mixins: FnvHashMap<_, _> = doc_mixins.iter()
+            .flat_map(|v| v.iter())
+            .map(|v| (&*v.name.name, v))
+            .collect();
+        for (name, doc_rules) in doc_rules {
+            self.add_rules(rules, doc_rules, &mixins)
+                .map_err(|err| syntax::FileError::from_parse_error(name, err))?;
+        }
+        Ok(())
+    }
+
+    fn add_rules<'a, 'm>(
+        &mut self, rules: &mut Rules<E>,
+        doc_rules: Vec<syntax::style::Rule<'a>>,
+        mixins: &FnvHashMap<&'m str, &'m syntax::style::Mixin<'a>>,
+    ) -> Result<(), syntax::PError<'a>> {
+        let mut flat = Vec::with_capacity(doc_rules.len());
+        for rule in doc_rules {
+            rule.flatten(&mut flat);
+        }
+        for rule in flat {
             let id = self.next_rule_id;
             self.next_rule_id = self.next_rule_id.wrapping_add(1);
-            self.rules.add(id, &mut self.static_keys, name, rule)?;
+            let mut styles = rule.styles;
+            let mut stack = Vec::new();
+            for include in rule.includes.iter().rev() {
+                include_mixin(&mut styles, mixins, include, &mut stack)?;
+            }
+            // Each selector in a list becomes its own rule sharing
+            // the same id and styles
+            let mut body = RuleBody {
+                source: styles,
+                compiled: Vec::new(),
+            };
+            for selector in rule.selectors {
+                rules.add(id, rule.priority, &mut self.static_keys, &self.atoms, &self.key_types, selector, &mut body)?;
+            }
         }
         Ok(())
     }
+
+    /// Replaces the rules in the named layer with the rules in
+    /// the document.
+    ///
+    /// The layer is left untouched if loading fails. Returns the
+    /// keys of nodes that may be affected by the change.
+    pub(crate) fn replace_styles<'a>(&mut self, name: &str, doc: syntax::style::Document<'a>) -> Result<FnvHashSet<RuleKey>, syntax::PError<'a>>{
+        let mut rules = Rules::new();
+        self.load_rules(&mut rules, doc)?;
+        Ok(self.replace_layer_rules(name, rules))
+    }
+
+    /// Replaces the rules in the named layer with the rules
+    /// in the files.
+    ///
+    /// The layer is left untouched if loading fails.
+    pub(crate) fn replace_styles_sources(&mut self, name: &str, sources: Vec<StyleSource>) -> Result<FnvHashSet<RuleKey>, syntax::FileError> {
+        let mut rules = Rules::new();
+        self.load_rules_sources(&mut rules, sources)?;
+        Ok(self.replace_layer_rules(name, rules))
+    }
+
+    fn replace_layer_rules(&mut self, name: &str, rules: Rules<E>) -> FnvHashSet<RuleKey> {
+        let idx = self.layer_index(name);
+        let old = ::std::mem::replace(&mut self.layers[idx].rules, rules);
+        let mut keys = self.layers[idx].rules.root_keys();
+        keys.extend(old.root_keys());
+        keys
+    }
+
+    /// Removes the named layer returning the keys of nodes
+    /// that may be affected by the change.
+    pub(crate) fn remove_layer(&mut self, name: &str) -> FnvHashSet<RuleKey> {
+        if let Some(idx) = self.find_layer(name) {
+            self.layers.remove(idx).rules.root_keys()
+        } else {
+            FnvHashSet::default()
+        }
+    }
+
+    /// Changes the priority of the named layer returning the keys
+    /// of nodes that may be affected by the change.
+    ///
+    /// Returns `None` if the layer doesn't exist.
+    pub(crate) fn set_layer_priority(&mut self, name: &str, priority: i32) -> Option<FnvHashSet<RuleKey>> {
+        let idx = self.find_layer(name)?;
+        let mut layer = self.layers.remove(idx);
+        let keys = layer.rules.root_keys();
+        layer.priority = priority;
+        self.insert_layer(layer);
+        Some(keys)
+    }
+
+    /// Enables or disables the named layer returning the keys
+    /// of nodes that may be affected by the change.
+    ///
+    /// Returns `None` if the layer doesn't exist.
+    pub(crate) fn set_layer_enabled(&mut self, name: &str, enabled: bool) -> Option<FnvHashSet<RuleKey>> {
+        let idx = self.find_layer(name)?;
+        let layer = &mut self.layers[idx];
+        if layer.enabled == enabled {
+            return Some(FnvHashSet::default());
+        }
+        layer.enabled = enabled;
+        Some(layer.rules.root_keys())
+    }
+
+    fn find_layer(&self, name: &str) -> Option<usize> {
+        self.layers.iter().position(|v| v.name == name)
+    }
+
+    /// Returns the index of the named layer, creating it if
+    /// it doesn't exist.
+    fn layer_index(&mut self, name: &str) -> usize {
+        if let Some(idx) = self.find_layer(name) {
+            idx
+        } else {
+            let order = self.next_layer_id;
+            self.next_layer_id = self.next_layer_id.wrapping_add(1);
+            self.insert_layer(StyleLayer {
+                name: name.into(),
+                order,
+                priority: 0,
+                enabled: true,
+                rules: Rules::new(),
+            })
+        }
+    }
+
+    fn insert_layer(&mut self, layer: StyleLayer<E>) -> usize {
+        // Layers with the same priority are kept in the order
+        // they were created in
+        let idx = self.layers.iter()
+            .position(|v| (v.priority, v.order) > (layer.priority, layer.order))
+            .unwrap_or(self.layers.len());
+        self.layers.insert(idx, layer);
+        idx
+    }
+
+    pub(crate) fn get_possible_matches(&self, node: &NodeChain<E>, out: &mut Vec<Rc<Rule<E>>>) {
+        for layer in self.layers.iter().filter(|v| v.enabled) {
+            layer.rules.get_possible_matches(node, out);
+        }
+
+        // Scoped rules are applied last with the closest scope
+        // taking precedence
+        let mut scopes = Vec::new();
+        let mut current = Some(node);
+        while let Some(n) = current {
+            if let Some(rules) = n.scoped_rules {
+                scopes.push(rules);
+            }
+            current = n.parent;
+        }
+        for rules in scopes.into_iter().rev() {
+            rules.get_possible_matches(node, out);
+        }
+    }
+}
+
+/// Merges the styles from the named mixin (and any mixins it includes)
+/// into the passed styles.
+///
+/// Styles that are already set are kept. Includes are expected to be
+/// processed last to first so that later includes take precedence.
+fn include_mixin<'a, 'm>(
+    styles: &mut FnvHashMap<syntax::Ident<'a>, syntax::style::ExprType<'a>>,
+    mixins: &FnvHashMap<&'m str, &'m syntax::style::Mixin<'a>>,
+    name: &syntax::Ident<'a>,
+    stack: &mut Vec<&'m str>,
+) -> Result<(), syntax::PError<'a>> {
+    let (key, mixin) = match mixins.get_key_value(&*name.name) {
+        Some(val) => val,
+        None => return Err(syntax::Errors::new(
+            name.position.into(),
+            syntax::Error::Message(syntax::Info::Borrowed("Unknown mixin")),
+        )),
+    };
+    if stack.contains(key) {
+        return Err(syntax::Errors::new(
+            name.position.into(),
+            syntax::Error::Message(syntax::Info::Borrowed("Mixin includes itself")),
+        ));
+    }
+    for (k, e) in &mixin.styles {
+        styles.entry(k.clone()).or_insert_with(|| e.clone());
+    }
+    stack.push(key);
+    for include in mixin.includes.iter().rev() {
+        include_mixin(styles, mixins, include, stack)?;
+    }
+    stack.pop();
+    Ok(())
+}
+
+/// A named set of rules that can be reordered, toggled and
+/// replaced as a whole.
+pub(crate) struct StyleLayer<E: Extension> {
+    name: String,
+    order: u32,
+    priority: i32,
+    enabled: bool,
+    rules: Rules<E>,
 }
 
 #[derive(Clone, Eq, Debug)]
@@ -116,15 +437,53 @@ pub struct Rules<E: Extension> {
     matches: Vec<Rc<Rule<E>>>,
 }
 
-#[derive(Debug)]
-pub enum ValueMatcher {
+pub enum ValueMatcher<E: Extension> {
     Boolean(bool),
     Integer(i32),
     Float(f64),
     String(String),
+    Color(Color),
+    ExtValue(E::Value),
     Exists,
 }
 
+/// Compiles the styles of a rule using the variables bound
+/// by its selector
+fn compile_styles<'a, E>(
+    keys: &FnvHashMap<&'static str, StaticKey>,
+    atoms: &FnvHashMap<StaticKey, FnvHashSet<&'static str>>,
+    types: &FnvHashMap<StaticKey, ValueKind>,
+    replacer: &PropertyReplacer,
+    rule_styles: &FnvHashMap<syntax::Ident<'a>, syntax::style::ExprType<'a>>,
+) -> Result<(FnvHashMap<StaticKey, Expr<E>>, UsedVariables), syntax::PError<'a>>
+    where E: Extension
+{
+    let mut styles = FnvHashMap::with_capacity_and_hasher(rule_styles.len(), Default::default());
+    let mut used_variables = UsedVariables::empty();
+    for (k, e) in rule_styles {
+
+        let key = match keys.get(&*k.name) {
+            Some(val) => val,
+            None => return Err(syntax::Errors::new(
+                k.position.into(),
+                syntax::Error::Message(syntax::Info::Borrowed("Unknown style key")),
+            )),
+        };
+        let position = e.position;
+        let expr = Expr::from_style(keys, replacer, &mut used_variables, atoms.get(key), e.clone())?;
+        if let (Expr::Value(ref val), Some(expected)) = (&expr, types.get(key)) {
+            if !expected.contains(ValueKind::of(val)) {
+                return Err(syntax::Errors::new(
+                    position.into(),
+                    syntax::Error::Message(syntax::Info::Borrowed("Incorrect type for this style key")),
+                ));
+            }
+        }
+        styles.insert(*key, expr);
+    }
+    Ok((styles, used_variables))
+}
+
 impl <E> Rules<E>
     where E: Extension
 {
@@ -135,24 +494,34 @@ impl <E> Rules<E>
         }
     }
 
-    fn add<'a>(&mut self, id: u32, keys: &mut FnvHashMap<&'static str, StaticKey>, name: &str, rule: syntax::style::Rule<'a>) -> Result<(), syntax::PError<'a>> {
+    #[allow(clippy::too_many_arguments)]
+    fn add<'a>(
+        &mut self,
+        id: u32,
+        priority: i32,
+        keys: &mut FnvHashMap<&'static str, StaticKey>,
+        atoms: &FnvHashMap<StaticKey, FnvHashSet<&'static str>>,
+        types: &FnvHashMap<StaticKey, ValueKind>,
+        selector: syntax::style::Selector<'a>,
+        body: &mut RuleBody<'a, E>,
+    ) -> Result<(), syntax::PError<'a>> {
         // Work in reverse to make lookups faster
         let mut current = self;
-        for m in rule.matchers.iter().rev() {
+        for m in selector.matchers.iter().rev() {
             let key = match m.0 {
                 syntax::style::Matcher::Text => RuleKeyBorrow::Text,
-                syntax::style::Matcher::Element(ref e) => RuleKeyBorrow::Element(e.name.name.into()),
+                syntax::style::Matcher::Element(ref e) => RuleKeyBorrow::Element(e.name.name.to_string()),
             };
             let tmp = current;
             let next = tmp.next.entry(RuleKey{inner: key}).or_insert_with(Rules::new);
             current = next;
         }
         let mut property_replacer = FnvHashMap::default();
-        let mut matchers = Vec::with_capacity(rule.matchers.len());
-        for (depth, m) in rule.matchers.into_iter().rev().enumerate() {
+        let mut matchers = Vec::with_capacity(selector.matchers.len());
+        for (depth, m) in selector.matchers.into_iter().rev().enumerate() {
             let key = match m.0 {
                 syntax::style::Matcher::Text => RuleKeyBorrow::Text,
-                syntax::style::Matcher::Element(ref e) => RuleKeyBorrow::Element(e.name.name.into()),
+                syntax::style::Matcher::Element(ref e) => RuleKeyBorrow::Element(e.name.name.to_string()),
             };
             let mut properties = Vec::with_capacity(m.1.len());
             for (k, v) in m.1 {
@@ -161,48 +530,65 @@ impl <E> Rules<E>
                     SVal::Boolean(b) => ValueMatcher::Boolean(b),
                     SVal::Integer(i) => ValueMatcher::Integer(i),
                     SVal::Float(f) => ValueMatcher::Float(f),
-                    SVal::String(s) => ValueMatcher::String(unescape(s)),
+                    SVal::String(s) => ValueMatcher::String(unescape(&s)),
+                    SVal::Color(c) => ValueMatcher::Color(c),
+                    SVal::Tagged(t, s) => match E::parse_literal(&t.name, &unescape(&s)) {
+                        Ok(v) => ValueMatcher::ExtValue(v),
+                        Err(err) => return Err(syntax::Errors::new(
+                            v.position.into(),
+                            syntax::Error::Message(syntax::Info::Borrowed(err)),
+                        )),
+                    },
                     SVal::Variable(n) => {
-                        property_replacer.insert(n.name.to_owned(), (depth, k.name.to_owned()));
+                        property_replacer.insert(n.name.into_owned(), (depth, k.name.to_string()));
                         ValueMatcher::Exists
                     }
                 };
-                properties.push((k.name.to_owned(), val));
+                properties.push((k.name.into_owned(), val));
             }
             matchers.push((RuleKey{inner: key}, properties));
         }
 
-        let mut styles = FnvHashMap::with_capacity_and_hasher(rule.styles.len(), Default::default());
-        let mut uses_parent_size = false;
-        for (k, e) in rule.styles {
-            let key = match keys.get(k.name) {
-                Some(val) => val,
-                None => return Err(syntax::Errors::new(
-                    k.position.into(),
-                    syntax::Error::Message(syntax::Info::Borrowed("Unknown style key")),
-                )),
-            };
-            styles.insert(*key, Expr::from_style(keys, &property_replacer, &mut uses_parent_size, e)?);
-        }
+        let (styles, used_variables) = match body.compiled.iter().find(|v| v.0 == property_replacer) {
+            Some(v) => (v.1.clone(), v.2),
+            None => {
+                let (styles, used_variables) = compile_styles(keys, atoms, types, &property_replacer, &body.source)?;
+                let styles = Rc::new(styles);
+                body.compiled.push((property_replacer, styles.clone(), used_variables));
+                (styles, used_variables)
+            },
+        };
+        let specificity = Specificity {
+            properties: matchers.iter().map(|v| v.1.len() as u32).sum(),
+            depth: matchers.len() as u32,
+        };
         current.matches.push(Rc::new(Rule {
             id,
-            name: name.into(),
+            priority,
+            specificity,
             matchers,
             styles,
-            uses_parent_size,
+            used_variables,
         }));
         Ok(())
     }
 
-    // Kinda expensive but shouldn't be common
-    pub fn remove_all_by_name(&mut self, name: &str) {
-        self.next.values_mut().for_each(|v| {
-            v.remove_all_by_name(name);
-        });
-        self.matches.retain(|v| v.name != name);
+    /// Returns the keys of the nodes that rules in this set
+    /// can be applied to.
+    pub(crate) fn root_keys(&self) -> FnvHashSet<RuleKey> {
+        self.next.keys().cloned().collect()
+    }
+
+    /// Moves the rules from `other` into this set
+    pub(crate) fn merge(&mut self, other: Rules<E>) {
+        self.matches.extend(other.matches);
+        for (k, v) in other.next {
+            self.next.entry(k).or_insert_with(Rules::new).merge(v);
+        }
     }
 
     pub(super) fn get_possible_matches(&self, node: &NodeChain<E>, out: &mut Vec<Rc<Rule<E>>>) {
+        let start = out.len();
         let mut current = self;
         let mut node = Some(node);
         while let Some(n) = node.take() {
@@ -220,26 +606,49 @@ impl <E> Rules<E>
             }
             node = n.parent;
         }
-        out.sort_unstable_by_key(|v| v.id);
+        out[start..].sort_unstable_by_key(|v| (v.priority, v.specificity, v.id));
     }
 }
 
+/// How specific a rule's matchers are.
+///
+/// Ordered by the number of property matchers first and
+/// then by the number of nodes in the hierarchy matched.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Specificity {
+    properties: u32,
+    depth: u32,
+}
+
 /// A rule which contains a set of matchers to compare against
 /// the properties of a node and parents and a set of styles to
 /// apply if matched.
 pub struct Rule<E: Extension> {
     id: u32,
-    name: String,
-    pub(crate) matchers: Vec<(RuleKey, Vec<(String, ValueMatcher)>)>,
+    priority: i32,
+    specificity: Specificity,
+    pub(crate) matchers: Vec<(RuleKey, PropertyMatchers<E>)>,
     #[doc(hidden)]
     // Used by the `eval!` macro
-    pub styles: FnvHashMap<StaticKey, Expr<E>>,
-    pub(crate) uses_parent_size: bool,
+    pub styles: RuleStyles<E>,
+    pub(crate) used_variables: UsedVariables,
 }
 
 impl <E> Rule<E>
     where E: Extension
 {
+    /// Creates a rule that applies inherited values
+    pub(super) fn new_inherited(styles: FnvHashMap<StaticKey, Expr<E>>) -> Rule<E> {
+        Rule {
+            id: 0,
+            priority: 0,
+            specificity: Specificity::default(),
+            matchers: Vec::new(),
+            styles: Rc::new(styles),
+            used_variables: UsedVariables::empty(),
+        }
+    }
+
     pub(super) fn test(&self, node: &NodeChain<E>) -> bool {
         let mut node = Some(node);
         for (_rkey, props) in &self.matchers {
@@ -256,6 +665,8 @@ impl <E> Rule<E>
                             (ValueMatcher::Float(a), Value::Float(b)) => *a == *b,
                             (ValueMatcher::Float(a), Value::Integer(b)) => *a == *b as f64,
                             (ValueMatcher::String(ref a), Value::String(ref b)) => a == b,
+                            (ValueMatcher::Color(a), Value::Color(b)) => *a == *b,
+                            (ValueMatcher::ExtValue(a), Value::ExtValue(b)) => a == b,
                             (ValueMatcher::Exists, _) => true,
                             (_, _) => false,
                         };
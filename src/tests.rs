@@ -20,6 +20,26 @@ impl Extension for TestExt {
         prop(CHAR);
     }
 
+    fn inherited_properties<'a, F>(mut prop: F)
+        where F: FnMut(StaticKey) + 'a
+    {
+        prop(CHAR);
+    }
+
+    fn style_types<'a, F>(mut ty: F)
+        where F: FnMut(StaticKey, ValueKind) + 'a
+    {
+        ty(CHAR, ValueKind::TEXT);
+    }
+
+    fn parse_literal(tag: &str, value: &str) -> Result<(), &'static str> {
+        match (tag, value) {
+            ("unit", "") => Ok(()),
+            ("unit", _) => Err("Unit literals must be empty"),
+            _ => Err("Unknown literal tag"),
+        }
+    }
+
     fn update_data(styles: &Styles<TestExt>, nc: &NodeChain<TestExt>, rule: &Rule<TestExt>, data: &mut Self::NodeData) -> DirtyFlags {
         eval!(styles, nc, rule.CHAR => val => {
             if let Some(c) = val.convert::<String>() {
@@ -165,4 +185,945 @@ inner {
 "##.trim();
 
     assert_eq!(layout, expected_output);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_selector_list() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+first, second > inner {
+    x = 1,
+    y = 1,
+    width = 2,
+    height = 1,
+    char = "@",
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    manager.add_node(node! {
+        first
+    });
+    manager.add_node(node! {
+        second {
+            inner
+        }
+    });
+
+    manager.layout(5, 3);
+
+    let mut render = AsciiRender::new(5, 3);
+    manager.render(&mut render);
+
+    let expected_output = r##"
+#####
+#@@##
+#####
+"##.trim();
+
+    assert_eq!(render.as_string(), expected_output);
+}
+
+#[test]
+fn test_rule_precedence() {
+    let src = r#"
+@text {
+    x = 0,
+    y = 0,
+    width = 1,
+    height = 1,
+}
+panel > @text {
+    char = "@",
+}
+@text(kind="alt") {
+    char = "+",
+}
+@text {
+    char = "=",
+}
+@priority(1) @text(kind="forced") {
+    char = "!",
+}
+@text(kind="forced") {
+    char = "?",
+}
+    "#;
+    let render_char = |node: Node<TestExt>| {
+        let mut manager: Manager<TestExt> = Manager::new();
+        manager.load_styles("test", src).unwrap();
+        manager.add_node(node);
+        manager.layout(1, 1);
+        let mut render = AsciiRender::new(1, 1);
+        manager.render(&mut render);
+        render.as_string()
+    };
+
+    // More specific rules win even when loaded first
+    assert_eq!(render_char(node!{ panel { @text("a") } }), "@");
+    // Property matchers beat hierarchy depth
+    let text = Node::new_text("a");
+    text.set_property("kind", "alt".to_owned());
+    let panel = node!{ panel };
+    panel.add_child(text);
+    assert_eq!(render_char(panel), "+");
+    // Equally specific rules fall back to load order
+    assert_eq!(render_char(Node::new_text("a")), "=");
+    // Explicit priorities override everything else
+    let text = Node::new_text("a");
+    text.set_property("kind", "forced".to_owned());
+    assert_eq!(render_char(text), "!");
+}
+
+#[test]
+fn test_style_layers() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    manager.load_styles("base", r#"
+box {
+    width = 1,
+    height = 1,
+    char = "a",
+}
+    "#).unwrap();
+    manager.load_styles("theme", r#"
+box {
+    char = "b",
+}
+    "#).unwrap();
+    manager.add_node(node!{ box });
+
+    let render_char = |manager: &mut Manager<TestExt>| {
+        manager.layout(1, 1);
+        let mut render = AsciiRender::new(1, 1);
+        manager.render(&mut render);
+        render.as_string()
+    };
+
+    // Later layers win by default
+    assert_eq!(render_char(&mut manager), "b");
+    assert!(manager.set_styles_priority("base", 1));
+    assert_eq!(render_char(&mut manager), "a");
+    assert!(manager.set_styles_enabled("base", false));
+    assert_eq!(render_char(&mut manager), "b");
+    assert!(manager.set_styles_enabled("base", true));
+    assert!(manager.set_styles_priority("base", 0));
+    // Missing layers aren't created
+    assert!(!manager.set_styles_priority("missing", 5));
+    assert!(!manager.set_styles_enabled("missing", false));
+
+    // Loading more rules into a layer keeps the existing ones
+    manager.load_styles("base", r#"
+box(x=1) {
+    char = "x",
+}
+    "#).unwrap();
+    assert!(manager.set_styles_priority("base", 1));
+    assert_eq!(render_char(&mut manager), "a");
+    assert!(manager.set_styles_priority("base", 0));
+
+    manager.replace_styles("theme", r#"
+box {
+    char = "c",
+}
+    "#).unwrap();
+    assert_eq!(render_char(&mut manager), "c");
+    // Failing to load keeps the old styles
+    assert!(manager.replace_styles("theme", r#"
+box {
+    unknown_key = "d",
+}
+    "#).is_err());
+    assert_eq!(render_char(&mut manager), "c");
+
+    manager.remove_styles("theme");
+    assert_eq!(render_char(&mut manager), "a");
+}
+
+#[test]
+fn test_mixins() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    manager.load_styles("test", r#"
+@mixin size {
+    width = 1,
+    height = 1,
+}
+@mixin base {
+    @include size,
+    char = "a",
+}
+@mixin alt {
+    char = "b",
+    x = 1,
+}
+first {
+    @include base,
+}
+second {
+    @include base,
+    @include alt,
+}
+third {
+    @include alt,
+    @include base,
+    char = "c",
+    x = 2,
+}
+    "#).unwrap();
+    manager.add_node(node!{ first });
+    manager.add_node(node!{ second });
+    manager.add_node(node!{ third });
+    manager.layout(3, 1);
+    let mut render = AsciiRender::new(3, 1);
+    manager.render(&mut render);
+    assert_eq!(render.as_string(), "abc");
+
+    assert!(manager.load_styles("missing", r#"
+first {
+    @include missing,
+}
+    "#).is_err());
+    assert!(manager.load_styles("cycle", r#"
+@mixin a {
+    @include b
+}
+@mixin b {
+    @include a
+}
+first {
+    @include a,
+}
+    "#).is_err());
+}
+
+#[test]
+fn test_scoped_styles() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    manager.load_styles("test", r#"
+@text {
+    width = 1,
+    height = 1,
+    char = "a",
+}
+@text(x=x) {
+    x = x,
+}
+    "#).unwrap();
+    let scoped = node!{
+        panel {
+            inner {
+                @text("b")
+            }
+        }
+    };
+    let text = Node::new_text("b");
+    text.set_property("x", 1);
+    scoped.add_child(text);
+    let other = Node::new_text("a");
+    other.set_property("x", 2);
+    manager.add_node(scoped.clone());
+    manager.add_node(other);
+
+    manager.load_scoped_styles(&scoped, r#"
+@text {
+    char = "b",
+}
+inner > @text {
+    char = "c",
+}
+    "#).unwrap();
+
+    let render_chars = |manager: &mut Manager<TestExt>| {
+        manager.layout(3, 1);
+        let mut render = AsciiRender::new(3, 1);
+        manager.render(&mut render);
+        render.as_string()
+    };
+    assert_eq!(render_chars(&mut manager), "cba");
+
+    manager.remove_scoped_styles(&scoped);
+    assert_eq!(render_chars(&mut manager), "aaa");
+}
+
+#[test]
+fn test_inherited_properties() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    manager.load_styles("test", r#"
+@text {
+    width = 1,
+    height = 1,
+}
+@text(x=x) {
+    x = x,
+}
+panel {
+    char = "p",
+}
+inner(override=true) {
+    char = "i",
+}
+    "#).unwrap();
+    let inner = node!{
+        inner {
+            @text("a")
+        }
+    };
+    let panel = node!{
+        panel {
+            @text("a")
+        }
+    };
+    panel.add_child(inner.clone());
+    let texts = panel.children().into_iter()
+        .chain(inner.children())
+        .filter(|v| v.text().is_some());
+    for (x, text) in texts.enumerate() {
+        text.set_property("x", x as i32);
+    }
+    manager.add_node(panel);
+
+    let render_chars = |manager: &mut Manager<TestExt>| {
+        manager.layout(2, 1);
+        let mut render = AsciiRender::new(2, 1);
+        manager.render(&mut render);
+        render.as_string()
+    };
+    assert_eq!(render_chars(&mut manager), "pp");
+
+    inner.set_property("override", true);
+    assert_eq!(render_chars(&mut manager), "pi");
+}
+
+#[test]
+fn test_inherited_core_properties() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    assert!(manager.add_inherited_property("width"));
+    assert!(!manager.add_inherited_property("missing"));
+    manager.load_styles("test", r#"
+panel {
+    width = 3,
+    height = 1,
+    char = "p",
+}
+@text {
+    height = 1,
+    char = "t",
+}
+broken {
+    char = parent.missing,
+}
+    "#).unwrap();
+    manager.add_node(node!{ panel { @text("a") } });
+    manager.add_node(node!{ broken });
+    manager.layout(4, 1);
+    let mut render = AsciiRender::new(4, 1);
+    manager.render(&mut render);
+    assert_eq!(render.as_string(), "ttt#");
+
+    // Inherited keys that fail are only reported once
+    let errors = manager.take_errors();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].key, CHAR);
+    assert_eq!(errors[0].error, Error::UnknownVariable{name: "missing".into()});
+}
+
+#[test]
+fn test_ancestor_properties() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    manager.load_styles("test", r#"
+@text {
+    width = 1,
+    height = 1,
+}
+@text(x=x) {
+    x = x,
+    char = ancestor(panel).c,
+}
+@text(y=y) {
+    y = y,
+    char = parent.c,
+}
+    "#).unwrap();
+    let inner = node!{
+        inner {
+            @text("a")
+        }
+    };
+    inner.children()[0].set_property("x", 0);
+    let panel = node!{
+        panel {
+            @text("a")
+        }
+    };
+    panel.children()[0].set_property("y", 0);
+    panel.children()[0].set_property("x", 1);
+    panel.add_child(inner.clone());
+    manager.add_node(panel.clone());
+
+    let render_chars = |manager: &mut Manager<TestExt>| {
+        manager.layout(2, 1);
+        let mut render = AsciiRender::new(2, 1);
+        manager.render(&mut render);
+        render.as_string()
+    };
+    // Missing properties leave the value unset
+    assert_eq!(render_chars(&mut manager), "##");
+
+    panel.set_property("c", "p".to_owned());
+    assert_eq!(render_chars(&mut manager), "pp");
+}
+
+#[test]
+fn test_builtin_variables() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    manager.load_styles("test", r#"
+@text {
+    x = index * 2 + sibling_count,
+    y = screen_height - 1,
+    width = 1,
+    height = 1,
+    char = "t",
+}
+panel {
+    width = child_count,
+    height = screen_height,
+    char = ".",
+}
+    "#).unwrap();
+    let panel = node!{
+        panel {
+            @text("a")
+            @text("b")
+        }
+    };
+    manager.add_node(panel.clone());
+
+    let render_chars = |manager: &mut Manager<TestExt>, width, height| {
+        manager.layout(width, height);
+        let mut render = AsciiRender::new(width as usize, height as usize);
+        manager.render(&mut render);
+        render.as_string()
+    };
+    assert_eq!(render_chars(&mut manager, 4, 1), ".t#t");
+
+    panel.add_child_first(Node::new_text("c"));
+    assert_eq!(render_chars(&mut manager, 7, 2), "...####\n..t#t#t");
+
+    let first = panel.children()[0].clone();
+    panel.remove_child(first);
+    assert_eq!(render_chars(&mut manager, 4, 1), ".t#t");
+}
+
+#[test]
+fn test_self_size() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    manager.load_styles("test", r#"
+panel {
+    x = 0,
+    y = 0,
+    width = self_width + 1,
+    height = 1,
+    char = "p",
+}
+    "#).unwrap();
+    manager.add_node(node!{ panel });
+
+    let render_chars = |manager: &mut Manager<TestExt>| {
+        manager.layout(4, 1);
+        let mut render = AsciiRender::new(4, 1);
+        manager.render(&mut render);
+        render.as_string()
+    };
+    // The node's own size is only used by the following layout
+    // so rules sizing the node from its own size still finish
+    assert_eq!(render_chars(&mut manager), "p###");
+    assert_eq!(render_chars(&mut manager), "pp##");
+    assert_eq!(render_chars(&mut manager), "ppp#");
+}
+
+#[test]
+fn test_templates() {
+    let node: Node<TestExt> = Node::from_str(r#"
+@template button(label, icon="button.png") {
+    button(style="default") {
+        image(src=$icon)
+        $label
+    }
+}
+@template toolbar(size) {
+    panel {
+        button(label=$size, style="small")
+    }
+}
+root {
+    button(label="Ok")
+    button(label=5, icon="ok.png", width=20) {
+        spacer
+    }
+    toolbar(size="s")
+}
+    "#).unwrap();
+    let children = node.children();
+    assert_eq!(children.len(), 3);
+
+    let ok = &children[0];
+    assert_eq!(ok.name(), Some("button".to_owned()));
+    assert_eq!(&*ok.get_property_ref::<String>("style").unwrap(), "default");
+    assert!(ok.get_property::<String>("label").is_none());
+    let parts = ok.children();
+    assert_eq!(&*parts[0].get_property_ref::<String>("src").unwrap(), "button.png");
+    assert_eq!(&*parts[1].text().unwrap(), "Ok");
+
+    let five = &children[1];
+    assert_eq!(five.get_property::<i32>("width"), Some(20));
+    let parts = five.children();
+    assert_eq!(parts.len(), 3);
+    assert_eq!(&*parts[0].get_property_ref::<String>("src").unwrap(), "ok.png");
+    assert_eq!(&*parts[1].text().unwrap(), "5");
+    assert_eq!(parts[2].name(), Some("spacer".to_owned()));
+
+    let small = &query!(children[2], panel > button).next().unwrap();
+    assert_eq!(&*small.get_property_ref::<String>("style").unwrap(), "small");
+    assert_eq!(&*small.children()[1].text().unwrap(), "s");
+}
+
+#[test]
+fn test_template_slots() {
+    let src = r#"
+@template window(title) {
+    window {
+        $title
+        content { @slot }
+        footer { @slot(footer) }
+    }
+}
+@template dialog {
+    window(title="Dialog") {
+        @slot
+        "Close"(slot="footer")
+    }
+}
+root {
+    dialog {
+        "Hello"
+        other(slot="missing")
+    }
+}
+    "#;
+    let node: Node<TestExt> = Node::from_str(src).unwrap();
+    let window = &node.children()[0];
+    let parts = window.children();
+    assert_eq!(parts.len(), 4);
+    assert_eq!(&*parts[0].text().unwrap(), "Dialog");
+    assert_eq!(&*parts[1].children()[0].text().unwrap(), "Hello");
+    let close = &parts[2].children()[0];
+    assert_eq!(&*close.text().unwrap(), "Close");
+    assert!(close.get_property::<String>("slot").is_none());
+    // Unknown slots are added to the root element
+    assert_eq!(parts[3].name(), Some("other".to_owned()));
+
+    let doc = syntax::desc::Document::parse(src).unwrap();
+    let window: Node<TestExt> = TemplateBuilder::new(&doc, "window")
+        .unwrap()
+        .arg("title", "Settings".to_owned())
+        .arg("width", 5)
+        .child(Node::new("options"))
+        .slot("footer", Node::new("close"))
+        .build()
+        .unwrap();
+    assert_eq!(window.get_property::<i32>("width"), Some(5));
+    let parts = window.children();
+    assert_eq!(&*parts[0].text().unwrap(), "Settings");
+    assert_eq!(parts[1].children()[0].name(), Some("options".to_owned()));
+    assert_eq!(parts[2].children()[0].name(), Some("close".to_owned()));
+}
+
+#[test]
+fn test_imports() {
+    let mut files = FnvHashMap::default();
+    files.insert("main.style", r#"
+@import "common.style"
+@text {
+    @include base,
+    char = "m",
+}
+    "#);
+    files.insert("common.style", r#"
+@mixin base {
+    width = 1,
+    height = 1,
+    char = "c",
+}
+    "#);
+    files.insert("cycle_a.style", r#"@import "cycle_b.style""#);
+    files.insert("cycle_b.style", r#"
+@text {
+    width = 1,
+}
+@import "cycle_a.style"
+    "#);
+    files.insert("main.desc", r#"
+@import "widgets.desc"
+root {
+    label(text="hi")
+}
+    "#);
+    files.insert("many.desc", "@for(item in items) { a }");
+    files.insert("widgets.desc", r#"
+@template label(text) {
+    label { $text }
+}
+    "#);
+    // Imports are relative to the importing file
+    files.insert("ui/menu.desc", r#"
+@import "parts/../button.desc"
+@import "../widgets.desc"
+menu {
+    button
+    label(text="hi")
+}
+    "#);
+    files.insert("ui/button.desc", r#"
+@template button {
+    button { "ok" }
+}
+    "#);
+    let mut resolver = |path: &str| files.get(path)
+        .map(|v| v.to_string())
+        .ok_or_else(|| "Not found".to_owned());
+
+    let node: Node<TestExt> = Node::from_file("main.desc", &mut resolver).unwrap();
+    assert_eq!(&*node.children()[0].children()[0].text().unwrap(), "hi");
+    let mut data = DataContext::new();
+    data.set_list("items", vec![1, 2]);
+    let err = Node::<TestExt>::from_file_with_data("many.desc", &mut resolver, &data).err().unwrap();
+    assert_eq!(err.message, "Expected a single root node");
+    let node: Node<TestExt> = Node::from_file("ui/menu.desc", &mut resolver).unwrap();
+    assert_eq!(&*node.children()[0].children()[0].text().unwrap(), "ok");
+    assert_eq!(&*node.children()[1].children()[0].text().unwrap(), "hi");
+
+    let mut manager: Manager<TestExt> = Manager::new();
+    manager.load_styles_file("main", "main.style", &mut resolver).unwrap();
+    manager.add_node(Node::new_text("a"));
+    manager.layout(1, 1);
+    let mut render = AsciiRender::new(1, 1);
+    manager.render(&mut render);
+    assert_eq!(render.as_string(), "m");
+
+    let err = manager.load_styles_file("cycle", "cycle_a.style", &mut resolver).unwrap_err();
+    assert_eq!(err.file, "cycle_b.style");
+    assert_eq!(err.message, "Import cycle: cycle_a.style -> cycle_b.style -> cycle_a.style");
+    assert_eq!(err.position.map(|v| v.line_number), Some(5));
+
+    let err = manager.load_styles_file("missing", "missing.style", &mut resolver).unwrap_err();
+    assert_eq!(err.to_string(), "missing.style: Not found");
+
+    assert!(manager.load_styles("inline", r#"@import "common.style""#).is_err());
+}
+
+#[test]
+fn test_fragments() {
+    let src = r#"
+@template overlay(name) {
+    overlay(name=$name)
+}
+overlay(name="tooltip")
+"Loading..."
+overlay(name="menu")
+    "#;
+    let nodes: Vec<Node<TestExt>> = Node::fragment_from_str(src).unwrap();
+    assert_eq!(nodes.len(), 3);
+    assert_eq!(nodes[0].get_property::<String>("name"), Some("tooltip".to_owned()));
+    assert_eq!(&*nodes[1].text().unwrap(), "Loading...");
+    assert_eq!(nodes[2].get_property::<String>("name"), Some("menu".to_owned()));
+
+    match Node::<TestExt>::from_str(src) {
+        Err(err) => assert_eq!(err.position.line, 6),
+        Ok(_) => panic!("Expected a single root error"),
+    }
+
+    let mut manager: Manager<TestExt> = Manager::new();
+    manager.add_nodes_str(src).unwrap();
+    assert_eq!(manager.query().name("overlay").matches().count(), 2);
+}
+
+#[test]
+fn test_conditional_nodes() {
+    let src = r#"
+@template entry(label) {
+    entry {
+        $label
+        @if(!label) { "Unnamed" }
+    }
+}
+menu {
+    @if(logged_in) {
+        "Log out"
+    } @else {
+        "Log in"
+    }
+    @for(item in entries) {
+        entry(label=$item)
+    }
+    @if(missing) { "Never" }
+    entry
+}
+    "#;
+    let mut data = DataContext::new();
+    data.set("logged_in", false);
+    data.set_list("entries", vec!["Open".to_owned(), "Save".to_owned()]);
+    let node: Node<TestExt> = Node::from_str_with_data(src, &data).unwrap();
+    let children = node.children();
+    assert_eq!(children.len(), 4);
+    assert_eq!(&*children[0].text().unwrap(), "Log in");
+    assert_eq!(&*children[1].children()[0].text().unwrap(), "Open");
+    assert_eq!(&*children[2].children()[0].text().unwrap(), "Save");
+    assert_eq!(&*children[3].children()[0].text().unwrap(), "Unnamed");
+
+    data.set("logged_in", true);
+    let node: Node<TestExt> = Node::from_str_with_data(src, &data).unwrap();
+    assert_eq!(&*node.children()[0].text().unwrap(), "Log out");
+
+    // A single root can still expand to several nodes
+    let err = Node::<TestExt>::from_str_with_data("@for(item in entries) { a(v=$item) }", &data).err().unwrap();
+    assert_eq!((err.position.line, err.position.column), (1, 1));
+    assert!(Node::<TestExt>::from_str_with_data("@if(missing) { a }", &data).is_err());
+
+    // Typos are reported instead of creating nothing
+    let err = Node::<TestExt>::from_str_with_data("a {\n    b(v=$mising)\n}", &data).err().unwrap();
+    assert_eq!((err.position.line, err.position.column), (2, 10));
+    let err = Node::<TestExt>::from_str_with_data("a { $mising }", &data).err().unwrap();
+    assert_eq!((err.position.line, err.position.column), (1, 6));
+    let err = Node::<TestExt>::from_str_with_data("a { @for(item in missing) { b } }", &data).err().unwrap();
+    assert_eq!((err.position.line, err.position.column), (1, 18));
+    let err = Node::<TestExt>::from_str_with_data("a { @for(item in logged_in) { b } }", &data).err().unwrap();
+    assert_eq!((err.position.line, err.position.column), (1, 18));
+    data.set("list", vec![1, 2]);
+    let err = Node::<TestExt>::from_str_with_data("a { $list }", &data).err().unwrap();
+    assert_eq!((err.position.line, err.position.column), (1, 6));
+    // Template parameters without a value are still skipped
+    let node = Node::<TestExt>::from_str("@template t(items) { a { @for(i in items) { b } } }\nt\n").unwrap();
+    assert!(node.children().is_empty());
+}
+
+#[test]
+fn test_print_nodes() {
+    let node: Node<TestExt> = node!{
+        panel(width=300, scale=2.0, visible=true, title="A \"quoted\"\\\ttitle".to_owned()) {
+            icon
+            label(size=-1.5) {
+                @text("Line one\nLine two")
+            }
+        }
+    };
+    let printed = node.to_string();
+    assert_eq!(printed, r#"panel(scale=2.0, title="A \"quoted\"\\\ttitle", visible=true, width=300) {
+    icon
+    label(size=-1.5) {
+        "Line one\nLine two"
+    }
+}
+"#);
+    let parsed: Node<TestExt> = Node::from_str(&printed).unwrap();
+    assert_eq!(parsed.to_string(), printed);
+    assert_eq!(
+        parsed.get_property::<String>("title"),
+        Some("A \"quoted\"\\\ttitle".to_owned())
+    );
+    assert_eq!(&*parsed.children()[1].children()[0].text().unwrap(), "Line one\nLine two");
+}
+
+#[test]
+fn test_print_unrepresentable() {
+    let node: Node<TestExt> = node!{
+        panel(width=300, a=f64::NAN, b=f64::INFINITY, c=vec![Value::Float(1.0), Value::Float(f64::NEG_INFINITY)])
+    };
+    node.set_property("$key", 5);
+    node.set_property("other key", 5);
+    node.set_property("atom", Value::Atom("not an atom".into()));
+    node.set_property("bool_atom", Value::Atom("true".into()));
+    node.set_property("num_atom", Value::Atom("5".into()));
+    node.set_property("atoms", vec![Value::Atom("left".into()), Value::Atom("false".into())]);
+    node.set_property("ok_atom", Value::Atom("center_2".into()));
+    let printed = node.to_string();
+    assert_eq!(printed, "panel(ok_atom=center_2, width=300)\n");
+    let parsed: Node<TestExt> = Node::from_str(&printed).unwrap();
+    assert_eq!(parsed.to_string(), printed);
+    assert!(parsed.get_property::<Value<TestExt>>("ok_atom") == Some(Value::Atom("center_2".into())));
+}
+
+#[test]
+fn test_literals() {
+    let node: Node<TestExt> = Node::from_str(r#"
+panel(color=#102030, width=0x10, scale=1.5e1) {
+    "caf\u{e9} \u{1F600}"
+}
+    "#).unwrap();
+    assert_eq!(node.get_property::<Color>("color"), Some(Color { r: 0x10, g: 0x20, b: 0x30, a: 255 }));
+    assert_eq!(node.get_property::<i32>("width"), Some(16));
+    assert_eq!(node.get_property::<f64>("scale"), Some(15.0));
+    assert_eq!(&*node.children()[0].text().unwrap(), "café 😀");
+    assert!(query!(node, panel(color=Color { r: 0x10, g: 0x20, b: 0x30, a: 255 })).next().is_some());
+
+    // A bare `\u` isn't a unicode escape
+    let node: Node<TestExt> = Node::from_str(r#"a(x="\u", y="\user", z="a\u{41}}b")"#).unwrap();
+    assert_eq!(node.get_property::<String>("x"), Some("u".to_owned()));
+    assert_eq!(node.get_property::<String>("y"), Some("user".to_owned()));
+    assert_eq!(node.get_property::<String>("z"), Some("aA}b".to_owned()));
+}
+
+#[test]
+fn test_raw_strings() {
+    let node: Node<TestExt> = Node::from_str(r##"
+/* A raw string keeps quotes and backslashes */
+panel(path=r"C:\files") {
+    r#"say("hi")\n"#
+}
+    "##).unwrap();
+    assert_eq!(node.get_property::<String>("path"), Some(r"C:\files".to_owned()));
+    assert_eq!(&*node.children()[0].text().unwrap(), r#"say("hi")\n"#);
+}
+
+#[test]
+fn test_atoms() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    manager.add_atoms(CHAR, &["a"]);
+    manager.load_styles("test", r#"
+first {
+    layout = absolute,
+    width = 1,
+    height = 1,
+    char = a,
+}
+second(mark=m) {
+    x = 1,
+    width = 1,
+    height = 1,
+    char = m,
+}
+    "#).unwrap();
+    let second: Node<TestExt> = Node::from_str("second(mark=b)").unwrap();
+    assert_eq!(second.get_property::<String>("mark"), Some("b".to_owned()));
+    assert!(second.get_property::<Value<TestExt>>("mark") == Some(Value::Atom("b".into())));
+    manager.add_node(node!{ first });
+    manager.add_node(second);
+    manager.layout(2, 1);
+    let mut render = AsciiRender::new(2, 1);
+    manager.render(&mut render);
+    assert_eq!(render.as_string(), "ab");
+
+    // Layout names are registered as atoms
+    assert!(manager.load_styles("typo", "first { layout = absolut }").is_err());
+
+    manager.add_atoms(CHAR, &["x", "y"]);
+    assert!(manager.load_styles("allowed", "first { char = x }").is_ok());
+    assert!(manager.load_styles("unknown", "first { char = z }").is_err());
+    // Typos of variables aren't treated as atoms
+    let err = manager.load_styles("typo", "first { char = parnet_width }").err().unwrap();
+    assert_eq!((err.position.line, err.position.column), (1, 16));
+    // Strings aren't checked
+    assert!(manager.load_styles("string", r#"first { char = "z" }"#).is_ok());
+}
+
+#[test]
+fn test_lists() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    manager.load_styles("test", r#"
+panel(sizes=sizes) {
+    layout = absolute,
+    width = sizes[1],
+    height = len([1]),
+    char = ["a", "b"][len("x")],
+}
+    "#).unwrap();
+    let panel: Node<TestExt> = Node::from_str("panel(sizes=[1, 2, 3])").unwrap();
+    assert_eq!(panel.get_property::<Vec<i32>>("sizes"), Some(vec![1, 2, 3]));
+    assert_eq!(panel.get_property::<Vec<String>>("sizes"), None);
+    manager.add_node(panel);
+    manager.layout(3, 1);
+    let mut render = AsciiRender::new(3, 1);
+    manager.render(&mut render);
+    assert_eq!(render.as_string(), "bb#");
+}
+
+#[test]
+fn test_tagged_literals() {
+    let node: Node<TestExt> = Node::from_str(r#"panel(value=unit"", list=[unit""])"#).unwrap();
+    assert!(node.get_property::<Value<TestExt>>("value") == Some(Value::ExtValue(())));
+    assert!(node.get_property::<Value<TestExt>>("list") == Some(Value::List(vec![Value::ExtValue(())])));
+
+    let err = Node::<TestExt>::from_str(r#"panel { "text"(v=unit"x") }"#).err().unwrap();
+    assert_eq!((err.position.line, err.position.column), (1, 18));
+    assert!(Node::<TestExt>::from_str(r#"@template t(v=nope"") { t } t"#).is_err());
+
+    let doc = syntax::desc::Document::parse(r#"panel(a=unit"", b=nope"", c=$param, d=[1])"#).unwrap();
+    let props = match doc.nodes[0] {
+        syntax::desc::Node::Element(ref e) => &e.properties,
+        _ => unreachable!(),
+    };
+    let prop = |name: &str| Value::<TestExt>::from_desc(props.iter().find(|v| v.0.name == name).unwrap().1);
+    assert!(prop("a").ok() == Some(Value::ExtValue(())));
+    assert!(prop("b").is_err());
+    assert!(prop("c").is_err());
+    assert!(prop("d").ok() == Some(Value::List(vec![Value::Integer(1)])));
+
+    let mut manager: Manager<TestExt> = Manager::new();
+    manager.load_styles("test", r#"
+panel(value=unit"") {
+    layout = absolute,
+    width = 1,
+    height = 1,
+    char = "u",
+}
+    "#).unwrap();
+    let err = manager.load_styles("bad", r#"panel { char = nope"" }"#).err().unwrap();
+    assert_eq!((err.position.line, err.position.column), (1, 16));
+    manager.add_node(node);
+    manager.layout(2, 1);
+    let mut render = AsciiRender::new(2, 1);
+    manager.render(&mut render);
+    assert_eq!(render.as_string(), "u#");
+}
+
+#[test]
+fn test_style_types() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let err = manager.load_styles("bad", "panel {\n    width = \"wide\",\n}").err().unwrap();
+    assert_eq!((err.position.line, err.position.column), (2, 13));
+    assert!(manager.load_styles("bad", "panel { char = 5 }").is_err());
+    assert!(manager.load_styles("bad", "panel { clip_overflow = 1 }").is_err());
+
+    manager.load_styles("test", r#"
+panel(w=w) {
+    layout = absolute,
+    x = [1, 2][3],
+    width = w,
+    height = 1.5,
+    char = "x",
+}
+    "#).unwrap();
+    // The same errors on both nodes are only reported once
+    manager.add_node_str(r#"panel(w="a")"#).unwrap();
+    manager.add_node_str(r#"panel(w="a")"#).unwrap();
+    manager.layout(2, 1);
+    let mut errors = manager.take_errors();
+    errors.sort_by_key(|v| v.key.0);
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0].key, WIDTH);
+    match errors[0].error {
+        Error::IncorrectType{found, ..} => assert_eq!(found, "string"),
+        ref e => panic!("Unexpected error: {:?}", e),
+    }
+    assert_eq!(errors[1].key, X);
+    assert_eq!(errors[1].error, Error::IndexOutOfRange{index: 3, len: 2});
+    assert_eq!(errors[1].expr, "([1, 2])[3]");
+    assert!(manager.take_errors().is_empty());
+
+    let list: Expr<TestExt> = Expr::Value(Value::List(vec![
+        Value::Integer(1),
+        Value::List(vec![Value::String("a".into()), Value::Atom("b".into())]),
+    ]));
+    assert_eq!(list.to_string(), r#"[1, ["a", b]]"#);
+}
@@ -4,21 +4,24 @@ use combine::parser::char::*;
 use combine::parser::range::*;
 use combine::error::*;
 use combine::Stream;
-use combine::stream::state::SourcePosition;
+use combine::easy;
+use combine::stream::state::{Positioner, SourcePosition, State};
 use combine::stream::StreamErrorFor;
-use super::Ident;
-use std::fmt::Debug;
+use super::{Color, Comment, Diagnostic, Ident, Import, Span};
+use std::borrow::Cow;
+use std::fmt::{self, Debug};
 
 pub(crate) fn ident<'a, I>() -> impl Parser<Input = I, Output = Ident<'a>>
     where
         I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
         <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
 {
-    (position(), take_while1(|c: char| c.is_alphanumeric() || c == '_'))
-        .map(|(pos, name): (_, &str)| {
+    (position(), take_while1(|c: char| c.is_alphanumeric() || c == '_'), position())
+        .map(|(pos, name, end): (_, &str, _)| {
             Ident {
-                name: name,
+                name: Cow::Borrowed(name),
                 position: SourcePosition::into(pos),
+                span: Span::new(pos, end),
             }
         })
 }
@@ -28,8 +31,10 @@ pub(crate) fn parse_bool<'a, I>() -> impl Parser<Input = I, Output = bool>
         I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
         <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
 {
-    try(string("true").map(|_| true))
-        .or(string("false").map(|_| false))
+    // Not part of a longer identifier such as `true_value`
+    let end = || not_followed_by(satisfy(|c: char| c.is_alphanumeric() || c == '_').map(|_| "identifier"));
+    try(string("true").skip(end()).map(|_| true))
+        .or(try(string("false").skip(end()).map(|_| false)))
 }
 
 pub(crate) fn parse_float<'a, I>() -> impl Parser<Input = I, Output = f64> + 'a
@@ -37,31 +42,90 @@ pub(crate) fn parse_float<'a, I>() -> impl Parser<Input = I, Output = f64> + 'a
         I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
         <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
 {
-    from_str(take_while1(|c: char| c.is_digit(10) || c == '.' || c == '-')
-        .and_then(|v: &str| if v.contains('.') {
+    // Either `1`, `1.` or `1.5` or `.5` followed by an
+    // optional exponent
+    let mantissa = choice((
+        (skip_many1(digit()), optional((token('.'), skip_many(digit())))).map(|_| ()),
+        (token('.'), skip_many1(digit())).map(|_| ()),
+    ));
+    let exponent = (one_of("eE".chars()), optional(one_of("+-".chars())), skip_many1(digit()));
+    from_str(recognize((optional(token('-')), mantissa, optional(attempt(exponent))))
+        .and_then(|v: &str| if v.contains(['.', 'e', 'E']) {
             Ok(v)
         } else {
             Err(StreamErrorFor::<I>::expected_static_message("float"))
         } ))
 }
 
-pub(crate) fn parse_integer<'a, I>() -> impl Parser<Input = I, Output = i32>
+/// Writes the float so that it is always parsed as a float
+/// (always containing a `.`)
+pub(crate) fn write_float<W: fmt::Write>(w: &mut W, v: f64) -> fmt::Result {
+    let v = v.to_string();
+    if v.contains('.') {
+        w.write_str(&v)
+    } else {
+        write!(w, "{}.0", v)
+    }
+}
+
+pub(crate) fn parse_integer<'a, I>() -> impl Parser<Input = I, Output = i32> + 'a
     where
         I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
         <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
 {
-    from_str(
-        Parser::expected(take_while1(|c: char| c.is_digit(10) || c == '-'), "integer")
+    let radix = |prefix: &'static str, radix: u32| (
+        attempt((optional(token('-')), string(prefix))),
+        take_while1(move |c: char| c.is_digit(radix)),
+    ).and_then(move |((neg, _), v): ((Option<char>, _), &str)| {
+        i64::from_str_radix(v, radix).ok()
+            .map(|v| if neg.is_some() { -v } else { v })
+            .filter(|v| *v >= i64::from(i32::MIN) && *v <= i64::from(i32::MAX))
+            .map(|v| v as i32)
+            .ok_or_else(|| StreamErrorFor::<I>::expected_static_message("integer"))
+    });
+    let decimal = from_str(recognize((optional(token('-')), skip_many1(digit()))));
+
+    Parser::expected(
+        choice((radix("0x", 16), radix("0b", 2), decimal)),
+        "integer",
     )
+}
 
+/// Parses a colour of the form `#rrggbb` or `#rrggbbaa`
+pub(crate) fn parse_color<'a, I>() -> impl Parser<Input = I, Output = Color> + 'a
+    where
+        I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
+        <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
+{
+    token('#')
+        .with(take_while1(|c: char| c.is_ascii_hexdigit()))
+        .and_then(|v: &str| {
+            if v.len() != 6 && v.len() != 8 {
+                return Err(StreamErrorFor::<I>::expected_static_message("colour"));
+            }
+            // Only ascii hex digits so this can't split a character
+            let part = |idx: usize| u8::from_str_radix(&v[idx .. idx + 2], 16)
+                .expect("Checked hex digits");
+            Ok(Color {
+                r: part(0),
+                g: part(2),
+                b: part(4),
+                a: if v.len() == 8 { part(6) } else { 255 },
+            })
+        })
 }
 
-pub(crate) fn parse_string<'a, I>() -> impl Parser<Input = I, Output = &'a str>
+/// Parses a quoted string (`"..."`) or a raw string
+/// (`r"..."`, `r#"..."#`) that doesn't process escapes.
+///
+/// Raw strings are returned in the escaped form used by
+/// quoted strings.
+pub(crate) fn parse_string<'a, I>() -> impl Parser<Input = I, Output = Cow<'a, str>> + 'a
     where
         I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
         <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
 {
-    (
+    let quoted = (
         token('"'),
         recognize(skip_many(
             try(string(r#"\""#).map(|_| '"'))
@@ -69,10 +133,253 @@ pub(crate) fn parse_string<'a, I>() -> impl Parser<Input = I, Output = &'a str>
                 .or(try(string(r#"\n"#).map(|_| '\n')))
                 .or(try(string(r#"\r"#).map(|_| '\r')))
                 .or(try(string(r#"\\"#).map(|_| '\\')))
+                .or(unicode_escape())
                 .or(satisfy(|c| c != '"')),
         )),
         token('"'),
-    ).map(|v| v.1)
+    ).map(|v| Cow::Borrowed(v.1));
+
+    let raw = attempt((token('r'), take_while(|c: char| c == '#'), token('"')))
+        .then(|(_, hashes, _): (_, &str, _)| {
+            // The string ends at a `"` followed by the same
+            // number of `#`s that it started with
+            let hashes = hashes.len();
+            let end = move || skip_count_min_max(hashes, hashes, token('#'));
+            recognize(skip_many(
+                satisfy(|c| c != '"').map(|_| ())
+                    .or(attempt(token('"').with(not_followed_by(end().map(|_| "end of string")))))
+            ))
+                .skip(token('"'))
+                .skip(end())
+        })
+        .map(|v: &'a str| if v.contains(['\\', '"']) {
+            Cow::Owned(escape_raw(v))
+        } else {
+            Cow::Borrowed(v)
+        });
+
+    quoted.or(raw)
+}
+
+/// Escapes the contents of a raw string so that it can be
+/// unescaped like a quoted string
+fn escape_raw(v: &str) -> String {
+    let mut out = String::with_capacity(v.len());
+    for c in v.chars() {
+        if c == '\\' || c == '"' {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Parses a `\u{..}` escape containing the hex
+/// value of the character
+fn unicode_escape<'a, I>() -> impl Parser<Input = I, Output = char> + 'a
+    where
+        I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
+        <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
+{
+    attempt(string(r#"\u{"#))
+        .with(take_while1(|c: char| c.is_ascii_hexdigit()))
+        .skip(token('}'))
+        .and_then(|v: &str| u32::from_str_radix(v, 16).ok()
+            .and_then(::std::char::from_u32)
+            .ok_or_else(|| StreamErrorFor::<I>::expected_static_message("unicode escape")))
+}
+
+/// A string or comment found whilst scanning the source
+enum Lexeme {
+    /// A quoted or raw string of the given length
+    String(usize),
+    /// A comment of the given length and its text
+    Comment(usize, bool),
+}
+
+/// Returns the string or comment that starts at the offset
+/// within the source, if any
+fn lex(source: &str, offset: usize) -> Option<Lexeme> {
+    let rest = &source[offset..];
+    if rest.starts_with('"') {
+        let mut chars = rest.char_indices().skip(1);
+        while let Some((idx, c)) = chars.next() {
+            match c {
+                '\\' => { chars.next(); },
+                '"' => return Some(Lexeme::String(idx + 1)),
+                _ => {},
+            }
+        }
+        return Some(Lexeme::String(rest.len()));
+    }
+    if rest.starts_with('r')
+        && !matches!(source[..offset].chars().next_back(), Some(c) if c.is_alphanumeric() || c == '_')
+    {
+        let hashes = rest[1..].len() - rest[1..].trim_start_matches('#').len();
+        if rest[1 + hashes..].starts_with('"') {
+            let body = 2 + hashes;
+            let mut end = String::from("\"");
+            end.extend((0 .. hashes).map(|_| '#'));
+            return Some(Lexeme::String(rest[body..].find(&end)
+                .map_or(rest.len(), |v| body + v + end.len())));
+        }
+    }
+    if rest.starts_with("//") {
+        // Trailing whitespace isn't part of the comment
+        let line = rest.find('\n').map_or(rest, |v| &rest[..v]);
+        return Some(Lexeme::Comment(line.trim_end().len(), false));
+    }
+    if let Some(body) = rest.strip_prefix("/*") {
+        return Some(Lexeme::Comment(body.find("*/").map_or(rest.len(), |v| v + 4), true));
+    }
+    None
+}
+
+/// Finds every comment within the source, ignoring any
+/// within strings
+pub(crate) fn collect_comments<'a>(source: &'a str) -> Vec<Comment<'a>> {
+    let mut comments = Vec::new();
+    let mut position = SourcePosition::new();
+    let mut offset = 0;
+    while let Some(c) = source[offset..].chars().next() {
+        let len = match lex(source, offset) {
+            Some(Lexeme::String(len)) => len,
+            Some(Lexeme::Comment(len, block)) => {
+                let comment = &source[offset .. offset + len];
+                let text = if block {
+                    comment[2..].strip_suffix("*/").unwrap_or(&comment[2..])
+                } else {
+                    &comment[2..]
+                };
+                let start = position;
+                for c in comment.chars() {
+                    position.update(&c);
+                }
+                comments.push(Comment {
+                    text: Cow::Borrowed(text),
+                    block,
+                    position: start.into(),
+                    span: Span::new(start, position),
+                });
+                offset += len;
+                continue;
+            },
+            None => c.len_utf8(),
+        };
+        for c in source[offset .. offset + len].chars() {
+            position.update(&c);
+        }
+        offset += len;
+    }
+    comments
+}
+
+/// Parses the source as a list of items, skipping over any
+/// items that fail to parse instead of stopping.
+///
+/// Parsing resumes after the first block (`{ }`) of the failed
+/// item, or after the line containing the error if the item
+/// doesn't start a block before then.
+pub(crate) fn parse_items_recovering<'a, F, P>(source: &'a str, mut item: F) -> (Vec<P::Output>, Vec<Diagnostic>)
+    where F: FnMut() -> P,
+          P: Parser<Input = easy::Stream<State<&'a str, SourcePosition>>>,
+{
+    let mut items = Vec::new();
+    let mut diagnostics = Vec::new();
+    let mut input = State::new(source);
+    loop {
+        input = match spaces().with(skip_many(skip_comment())).easy_parse(input.clone()) {
+            Ok((_, rest)) => rest,
+            Err(_) => input,
+        };
+        if input.input.is_empty() {
+            break;
+        }
+        match item().easy_parse(input.clone()) {
+            Ok((v, rest)) => {
+                items.push(v);
+                input = rest;
+            },
+            Err(err) => {
+                let start = source.len() - input.input.len();
+                let error = offset_of(source, err.position);
+                diagnostics.push(Diagnostic::from_parse_error(err));
+                let resume = recovery_point(source, start, error);
+                input = State {
+                    input: &source[resume..],
+                    positioner: position_at(source, resume),
+                };
+            },
+        }
+    }
+    (items, diagnostics)
+}
+
+/// Finds where to continue parsing after an item starting at
+/// `start` failed to parse at `error`
+fn recovery_point(source: &str, start: usize, error: usize) -> usize {
+    let line_end = source[error..].find('\n').map_or(source.len(), |v| error + v + 1);
+    let mut depth = 0;
+    let mut idx = start;
+    while let Some(c) = source[idx..].chars().next() {
+        match lex(source, idx) {
+            Some(Lexeme::String(len)) | Some(Lexeme::Comment(len, _)) => {
+                idx += len;
+                continue;
+            },
+            None => {},
+        }
+        match c {
+            '{' => {
+                // A block starting after the error's line
+                // belongs to the next item
+                if depth == 0 && idx >= line_end {
+                    return line_end;
+                }
+                depth += 1;
+            },
+            '}' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    return if idx >= error {
+                        idx + 1
+                    } else {
+                        line_end.max(idx + 1)
+                    };
+                }
+            },
+            _ => {},
+        }
+        idx += c.len_utf8();
+    }
+    if depth == 0 {
+        line_end
+    } else {
+        source.len()
+    }
+}
+
+/// Returns the byte offset of the position within the source
+fn offset_of(source: &str, position: SourcePosition) -> usize {
+    let mut current = SourcePosition::new();
+    for (idx, c) in source.char_indices() {
+        if current.line >= position.line && current.column >= position.column
+            || current.line > position.line
+        {
+            return idx;
+        }
+        current.update(&c);
+    }
+    source.len()
+}
+
+/// Returns the position of the byte offset within the source
+fn position_at(source: &str, offset: usize) -> SourcePosition {
+    let mut current = SourcePosition::new();
+    for c in source[..offset].chars() {
+        current.update(&c);
+    }
+    current
 }
 
 pub(crate) fn skip_comment<'a, I>() -> impl Parser<Input = I, Output = ()>
@@ -80,8 +387,35 @@ pub(crate) fn skip_comment<'a, I>() -> impl Parser<Input = I, Output = ()>
         I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
         <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
 {
-    string("//")
-        .with(skip_many(satisfy(|c| c != '\n')))
+    let line = attempt(string("//"))
+        .with(skip_many(satisfy(|c| c != '\n')));
+    let block = attempt(string("/*"))
+        .with(skip_many(
+            satisfy(|c| c != '*').map(|_| ())
+                .or(attempt(token('*').with(not_followed_by(token('/')))))
+        ))
+        .with(string("*/"))
+        .map(|_| ());
+    line.or(block)
         .with(spaces())
         .map(|_| ())
-}
\ No newline at end of file
+}
+
+pub(crate) fn parse_import<'a, I>() -> impl Parser<Input = I, Output = Import<'a>>
+    where
+        I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
+        <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
+{
+    let comments = skip_many(skip_comment());
+
+    attempt(spaces().with(comments).with(look_ahead(string("@import"))))
+        .with((
+            position(),
+            string("@import").skip(skip_many1(space())),
+            parse_string(),
+        ))
+        .map(|v| Import {
+            path: v.2,
+            position: SourcePosition::into(v.0),
+        })
+}
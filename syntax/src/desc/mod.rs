@@ -6,7 +6,9 @@
 //! The format is as follows:
 //!
 //! ```text,ignore
-//! // Comments (only single line)
+//! // Single line comments
+//! /* Block
+//!    comments */
 //!
 //! // Name of an element. Can be made up from any
 //! // letter, number or _
@@ -20,12 +22,65 @@
 //!     image(src="example.png", width=150, height=150) {
 //!
 //!     }
+//!     // Values can also be bare identifiers (atoms)
+//!     label(align=center)
+//!     // Strings can be tagged with a name to create
+//!     // values that are parsed by the program
+//!     image(src=img"ui/button")
+//!     // Lists are written within []
+//!     grid(columns=[100, 50, 100])
 //!     // {} is optional
 //!     emoji(type="smile")
 //!     // As is ()
 //!     spacer
 //!     // Text can be used as well (quoted)
 //!     "Hello world"
+//!     // Raw strings don't process escapes
+//!     r#"Say "hello""#
+//!     // Templates are used like elements
+//!     button(label="Ok")
+//! }
+//!
+//! // Templates can be imported from other files. How the
+//! // path is resolved is decided by the program loading
+//! // the document.
+//! @import "widgets.desc"
+//!
+//! // Templates are expanded where they are used. Properties
+//! // matching a parameter are substituted where `$name` is
+//! // used, any other properties and children are added to
+//! // the template's root element.
+//! @template button(label, icon="button.png") {
+//!     button {
+//!         image(src=$icon)
+//!         $label
+//!     }
+//! }
+//! // `@slot` marks where the caller's children are placed.
+//! // Children with a `slot` property are placed in the
+//! // slot with the same name instead.
+//! @template window(title) {
+//!     window {
+//!         title { $title }
+//!         content { @slot }
+//!         footer { @slot(footer) }
+//!     }
+//! }
+//!
+//! // Nodes can be included depending on values supplied by
+//! // the program when the document is loaded. `@if(name)`
+//! // includes its nodes when the value is set and isn't `false`,
+//! // `@if(!name)` when it isn't.
+//! menu {
+//!     @if(logged_in) {
+//!         "Log out"
+//!     } @else {
+//!         "Log in"
+//!     }
+//!     // `@for` repeats its nodes for every item in a list
+//!     @for(item in entries) {
+//!         entry(action=$item) { $item }
+//!     }
 //! }
 //! ```
 
@@ -38,16 +93,25 @@ use combine::error::*;
 use combine::Stream;
 use combine::easy::{ParseError,};
 use combine::stream::state::{State, SourcePosition};
-use super::{Ident, Position};
-use std::fmt::Debug;
+use super::{Color, Comment, Diagnostic, Ident, Import, Position, Span};
+use std::fmt::{self, Debug, Display, Formatter};
+use std::borrow::Cow;
 
 /// A UI description document
 ///
-/// Currently a document is made up of a single element.
+/// A document is made up of any number of top level
+/// nodes, templates and imports.
 #[derive(Debug)]
 pub struct Document<'a> {
-    /// The root element of the element
-    pub root: Element<'a>,
+    /// The top level nodes of the document
+    pub nodes: Vec<Node<'a>>,
+    /// A list of templates defined in this document
+    pub templates: Vec<Template<'a>>,
+    /// A list of files imported by this document
+    pub imports: Vec<Import<'a>>,
+    /// The comments within this document in the order
+    /// they appear
+    pub comments: Vec<Comment<'a>>,
 }
 
 impl <'a> Document<'a> {
@@ -70,9 +134,37 @@ impl <'a> Document<'a> {
     ///
     /// [`format_parse_error`]: ../fn.format_parse_error.html
     pub fn parse(source: &str) -> Result<Document, ParseError<State<&str, SourcePosition>>> {
-        let (doc, _) = parse_document().easy_parse(State::new(source))?;
+        let (mut doc, _) = parse_document().easy_parse(State::new(source))?;
+        doc.comments = collect_comments(source);
         Ok(doc)
     }
+
+    /// Parses the given string as a document, skipping over
+    /// top level nodes, templates and imports that contain
+    /// syntax errors instead of failing.
+    ///
+    /// Returns the document made up of the items that parsed
+    /// along with an error for every skipped item.
+    pub fn parse_recovering(source: &'a str) -> (Document<'a>, Vec<Diagnostic>) {
+        let (items, diagnostics) = parse_items_recovering(source, parse_item);
+        let mut doc = Document::from_items(items);
+        doc.comments = collect_comments(source);
+        (doc, diagnostics)
+    }
+
+    fn from_items(items: Vec<Item<'a>>) -> Document<'a> {
+        let mut nodes = Vec::new();
+        let mut templates = Vec::new();
+        let mut imports = Vec::new();
+        for item in items {
+            match item {
+                Item::Node(n) => nodes.push(n),
+                Item::Template(t) => templates.push(t),
+                Item::Import(i) => imports.push(i),
+            }
+        }
+        Document { nodes, templates, imports, comments: Vec::new() }
+    }
 }
 
 /// An element which can contain other elements and/or
@@ -89,11 +181,15 @@ pub struct Element<'a> {
     pub properties: FnvHashMap<Ident<'a>, ValueType<'a>>,
     /// Optional list of nodes within this element
     pub nodes: Vec<Node<'a>>,
+    /// The source range of the element including its
+    /// properties and nodes
+    pub span: Span,
 }
 
 /// A node that can be contained within an element.
 ///
-/// This is either another element or raw text.
+/// This is either another element, raw text or a
+/// template parameter.
 #[derive(Debug)]
 pub enum Node<'a> {
     /// A sub element
@@ -102,7 +198,148 @@ pub enum Node<'a> {
     ///
     /// Position is the position of the text within
     /// the source (used for debugging)
-    Text(&'a str, Position, FnvHashMap<Ident<'a>, ValueType<'a>>),
+    Text(Cow<'a, str>, Position, FnvHashMap<Ident<'a>, ValueType<'a>>),
+    /// Text taken from a template parameter (`$name`)
+    Parameter(Ident<'a>, FnvHashMap<Ident<'a>, ValueType<'a>>),
+    /// Marks where the children of a template's caller
+    /// are placed (`@slot` or `@slot(name)`)
+    Slot(Option<Ident<'a>>, Position),
+    /// Nodes that are only included if a condition is met
+    If(If<'a>),
+    /// Nodes that are repeated for every item in a list
+    For(For<'a>),
+}
+
+impl <'a> Document<'a> {
+    /// Converts the document into one that doesn't borrow
+    /// the source so that it can be cached or sent to
+    /// other threads.
+    pub fn into_owned(self) -> Document<'static> {
+        Document {
+            nodes: owned_nodes(self.nodes),
+            templates: self.templates.into_iter().map(Template::into_owned).collect(),
+            imports: self.imports.into_iter().map(Import::into_owned).collect(),
+            comments: self.comments.into_iter().map(Comment::into_owned).collect(),
+        }
+    }
+}
+
+impl <'a> Element<'a> {
+    /// Converts the element into one that doesn't borrow
+    /// the source
+    pub fn into_owned(self) -> Element<'static> {
+        Element {
+            name: self.name.into_owned(),
+            properties: owned_properties(self.properties),
+            nodes: owned_nodes(self.nodes),
+            span: self.span,
+        }
+    }
+}
+
+impl <'a> Node<'a> {
+    /// Converts the node into one that doesn't borrow
+    /// the source
+    pub fn into_owned(self) -> Node<'static> {
+        match self {
+            Node::Element(e) => Node::Element(e.into_owned()),
+            Node::Text(t, pos, props) => Node::Text(Cow::Owned(t.into_owned()), pos, owned_properties(props)),
+            Node::Parameter(n, props) => Node::Parameter(n.into_owned(), owned_properties(props)),
+            Node::Slot(n, pos) => Node::Slot(n.map(Ident::into_owned), pos),
+            Node::If(v) => Node::If(If {
+                name: v.name.into_owned(),
+                negated: v.negated,
+                nodes: owned_nodes(v.nodes),
+                else_nodes: owned_nodes(v.else_nodes),
+                position: v.position,
+            }),
+            Node::For(v) => Node::For(For {
+                item: v.item.into_owned(),
+                list: v.list.into_owned(),
+                nodes: owned_nodes(v.nodes),
+                position: v.position,
+            }),
+        }
+    }
+
+    /// Returns the position of the node within the source
+    pub fn position(&self) -> Position {
+        match *self {
+            Node::Element(ref e) => e.name.position,
+            Node::Text(_, pos, _) => pos,
+            Node::Parameter(ref n, _) => n.position,
+            Node::Slot(_, pos) => pos,
+            Node::If(ref v) => v.position,
+            Node::For(ref v) => v.position,
+        }
+    }
+}
+
+/// Nodes included depending on whether a value is
+/// set (`@if(name) { } @else { }`)
+#[derive(Debug)]
+pub struct If<'a> {
+    /// The name of the value being tested
+    pub name: Ident<'a>,
+    /// Whether the test is inverted (`@if(!name)`)
+    pub negated: bool,
+    /// The nodes used when the test passes
+    pub nodes: Vec<Node<'a>>,
+    /// The nodes used when the test fails
+    pub else_nodes: Vec<Node<'a>>,
+    /// The position of the `@if` within the source
+    pub position: Position,
+}
+
+/// Nodes repeated for every item in a list
+/// (`@for(item in list) { }`)
+#[derive(Debug)]
+pub struct For<'a> {
+    /// The name each item is bound to
+    pub item: Ident<'a>,
+    /// The name of the list
+    pub list: Ident<'a>,
+    /// The nodes created for each item
+    pub nodes: Vec<Node<'a>>,
+    /// The position of the `@for` within the source
+    pub position: Position,
+}
+
+/// A reusable element structure that is expanded
+/// wherever an element with the same name is used.
+#[derive(Debug)]
+pub struct Template<'a> {
+    /// The name of the template
+    pub name: Ident<'a>,
+    /// The parameters of the template and their
+    /// default values if any
+    pub parameters: Vec<(Ident<'a>, Option<ValueType<'a>>)>,
+    /// The root element of the template
+    pub root: Element<'a>,
+}
+
+impl <'a> Template<'a> {
+    /// Converts the template into one that doesn't borrow
+    /// the source
+    pub fn into_owned(self) -> Template<'static> {
+        Template {
+            name: self.name.into_owned(),
+            parameters: self.parameters.into_iter()
+                .map(|(n, v)| (n.into_owned(), v.map(ValueType::into_owned)))
+                .collect(),
+            root: self.root.into_owned(),
+        }
+    }
+}
+
+fn owned_nodes(nodes: Vec<Node>) -> Vec<Node<'static>> {
+    nodes.into_iter().map(Node::into_owned).collect()
+}
+
+fn owned_properties(props: FnvHashMap<Ident, ValueType>) -> FnvHashMap<Ident<'static>, ValueType<'static>> {
+    props.into_iter()
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect()
 }
 
 /// Contains a value and debugging information
@@ -115,6 +352,30 @@ pub struct ValueType<'a> {
     ///
     /// Used for debugging.
     pub position: Position,
+    /// The source range of the value
+    pub span: Span,
+}
+
+impl <'a> ValueType<'a> {
+    /// Converts the value into one that doesn't borrow
+    /// the source
+    pub fn into_owned(self) -> ValueType<'static> {
+        ValueType {
+            value: match self.value {
+                Value::Boolean(v) => Value::Boolean(v),
+                Value::Integer(v) => Value::Integer(v),
+                Value::Float(v) => Value::Float(v),
+                Value::Color(v) => Value::Color(v),
+                Value::String(v) => Value::String(Cow::Owned(v.into_owned())),
+                Value::Parameter(v) => Value::Parameter(v.into_owned()),
+                Value::Atom(v) => Value::Atom(v.into_owned()),
+                Value::List(v) => Value::List(v.into_iter().map(ValueType::into_owned).collect()),
+                Value::Tagged(t, v) => Value::Tagged(t.into_owned(), Cow::Owned(v.into_owned())),
+            },
+            position: self.position,
+            span: self.span,
+        }
+    }
 }
 
 /// A parsed value for a property
@@ -127,7 +388,184 @@ pub enum Value<'a> {
     /// A 64 bit float (of the form `0.0`)
     Float(f64),
     /// A quoted string
-    String(&'a str),
+    String(Cow<'a, str>),
+    /// A colour (`#rrggbb` or `#rrggbbaa`)
+    Color(Color),
+    /// A template parameter (`$name`)
+    Parameter(Ident<'a>),
+    /// A bare identifier (`center`)
+    Atom(Ident<'a>),
+    /// A list of values (`[1, 2, 3]`)
+    List(Vec<ValueType<'a>>),
+    /// A string tagged with a name (`img"ui/button"`)
+    /// that is parsed by the program
+    Tagged(Ident<'a>, Cow<'a, str>),
+}
+
+/// Prints the document in the description format.
+///
+/// Imports are printed first followed by templates and
+/// then the top level nodes. Properties are sorted by name.
+impl <'a> Display for Document<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut sep = false;
+        for import in &self.imports {
+            writeln!(f, "@import \"{}\"", import.path)?;
+            sep = true;
+        }
+        for template in &self.templates {
+            if sep {
+                writeln!(f)?;
+            }
+            write!(f, "{}", template)?;
+            sep = true;
+        }
+        if sep && !self.nodes.is_empty() {
+            writeln!(f)?;
+        }
+        write_nodes(f, &self.nodes, 0)
+    }
+}
+
+impl <'a> Display for Template<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "@template {}", self.name.name)?;
+        if !self.parameters.is_empty() {
+            write!(f, "(")?;
+            for (idx, (name, default)) in self.parameters.iter().enumerate() {
+                if idx != 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", name.name)?;
+                if let Some(default) = default {
+                    write!(f, "={}", default)?;
+                }
+            }
+            write!(f, ")")?;
+        }
+        writeln!(f, " {{")?;
+        write_element(f, &self.root, 1)?;
+        writeln!(f, "}}")
+    }
+}
+
+impl <'a> Display for Node<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write_node(f, self, 0)
+    }
+}
+
+impl <'a> Display for ValueType<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        Display::fmt(&self.value, f)
+    }
+}
+
+impl <'a> Display for Value<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Value::Boolean(v) => write!(f, "{}", v),
+            Value::Integer(v) => write!(f, "{}", v),
+            Value::Float(v) => write_float(f, v),
+            Value::Color(v) => write!(f, "{}", v),
+            Value::String(ref v) => write!(f, "\"{}\"", v),
+            Value::Parameter(ref v) => write!(f, "${}", v.name),
+            Value::Atom(ref v) => write!(f, "{}", v.name),
+            Value::Tagged(ref t, ref v) => write!(f, "{}\"{}\"", t.name, v),
+            Value::List(ref v) => {
+                write!(f, "[")?;
+                for (idx, v) in v.iter().enumerate() {
+                    if idx != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", v)?;
+                }
+                write!(f, "]")
+            },
+        }
+    }
+}
+
+fn write_indent(f: &mut Formatter, indent: usize) -> fmt::Result {
+    for _ in 0 .. indent {
+        write!(f, "    ")?;
+    }
+    Ok(())
+}
+
+fn write_nodes(f: &mut Formatter, nodes: &[Node], indent: usize) -> fmt::Result {
+    for node in nodes {
+        write_node(f, node, indent)?;
+    }
+    Ok(())
+}
+
+/// Writes the nodes within `{}` followed by a new line
+fn write_body(f: &mut Formatter, nodes: &[Node], indent: usize) -> fmt::Result {
+    writeln!(f, "{{")?;
+    write_nodes(f, nodes, indent + 1)?;
+    write_indent(f, indent)?;
+    write!(f, "}}")
+}
+
+fn write_properties(f: &mut Formatter, props: &FnvHashMap<Ident, ValueType>) -> fmt::Result {
+    if props.is_empty() {
+        return Ok(());
+    }
+    let mut props: Vec<_> = props.iter().collect();
+    props.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+    write!(f, "(")?;
+    for (idx, (name, value)) in props.into_iter().enumerate() {
+        if idx != 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}={}", name.name, value)?;
+    }
+    write!(f, ")")
+}
+
+fn write_element(f: &mut Formatter, element: &Element, indent: usize) -> fmt::Result {
+    write_indent(f, indent)?;
+    write!(f, "{}", element.name.name)?;
+    write_properties(f, &element.properties)?;
+    if !element.nodes.is_empty() {
+        write!(f, " ")?;
+        write_body(f, &element.nodes, indent)?;
+    }
+    writeln!(f)
+}
+
+fn write_node(f: &mut Formatter, node: &Node, indent: usize) -> fmt::Result {
+    if let Node::Element(ref e) = *node {
+        return write_element(f, e, indent);
+    }
+    write_indent(f, indent)?;
+    match *node {
+        Node::Element(_) => unreachable!(),
+        Node::Text(ref text, _, ref props) => {
+            write!(f, "\"{}\"", text)?;
+            write_properties(f, props)?;
+        },
+        Node::Parameter(ref name, ref props) => {
+            write!(f, "${}", name.name)?;
+            write_properties(f, props)?;
+        },
+        Node::Slot(Some(ref name), _) => write!(f, "@slot({})", name.name)?,
+        Node::Slot(None, _) => write!(f, "@slot")?,
+        Node::If(ref v) => {
+            write!(f, "@if({}{}) ", if v.negated { "!" } else { "" }, v.name.name)?;
+            write_body(f, &v.nodes, indent)?;
+            if !v.else_nodes.is_empty() {
+                write!(f, " @else ")?;
+                write_body(f, &v.else_nodes, indent)?;
+            }
+        },
+        Node::For(ref v) => {
+            write!(f, "@for({} in {}) ", v.item.name, v.list.name)?;
+            write_body(f, &v.nodes, indent)?;
+        },
+    }
+    writeln!(f)
 }
 
 fn parse_document<'a, I>() -> impl Parser<Input = I, Output = Document<'a>>
@@ -135,9 +573,68 @@ fn parse_document<'a, I>() -> impl Parser<Input = I, Output = Document<'a>>
         I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
         <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
 {
+    let item = parse_item()
+        .skip(spaces())
+        .skip(skip_many(skip_comment()));
     spaces()
-        .with(parse_element())
-        .map(|e| Document { root: e })
+        .with(skip_many(skip_comment()))
+        .with(many(item))
+        .map(Document::from_items)
+}
+
+/// A top level item within a document
+enum Item<'a> {
+    Node(Node<'a>),
+    Template(Template<'a>),
+    Import(Import<'a>),
+}
+
+fn parse_item<'a, I>() -> impl Parser<Input = I, Output = Item<'a>>
+    where
+        I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
+        <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
+{
+    parse_import().map(Item::Import)
+        .or(parse_template().map(Item::Template))
+        .or(parse_node().map(Item::Node))
+}
+
+fn parse_template<'a, I>() -> impl Parser<Input = I, Output = Template<'a>>
+    where
+        I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
+        <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
+{
+    let comments = skip_many(skip_comment());
+
+    let parameter = (
+        spaces().with(ident()),
+        optional(attempt(spaces().with(token('=')))
+            .with(spaces().with(value()))),
+    );
+    let parameters = (
+        token('('),
+        sep_end_by(parameter, attempt(spaces().with(token(',')))),
+        spaces().with(token(')')),
+    ).map(|(_, l, _)| l);
+
+    let template = (
+        string("@template").skip(skip_many1(space())),
+        ident(),
+        spaces().with(optional(parameters)),
+        spaces().with(token('{')),
+        parse_element(),
+        spaces().with(skip_many(skip_comment())).with(token('}')),
+    );
+
+    attempt(spaces().with(comments).with(look_ahead(string("@template"))))
+        .with(template)
+        .map(|v| {
+            Template {
+                name: v.1,
+                parameters: v.2.unwrap_or_default(),
+                root: v.4,
+            }
+        })
 }
 
 fn parse_element<'a, I>() -> impl Parser<Input = I, Output = Element<'a>>
@@ -149,18 +646,23 @@ fn parse_element<'a, I>() -> impl Parser<Input = I, Output = Element<'a>>
 
     let element = (
         ident().skip(look_ahead(char('{').or(char('(')).or(space()).map(|_| ()))),
-        spaces().with(optional(properties())),
-        spaces().with(optional(parser(body))),
+        spaces().with(optional((properties(), position()))),
+        spaces().with(optional((parser(body), position()))),
     );
 
     spaces()
         .with(comments)
         .with(element)
         .map(|v| {
+            // The element ends at whichever part was parsed last
+            let end = v.2.as_ref().map(|b| b.1)
+                .or_else(|| v.1.as_ref().map(|p| p.1))
+                .map_or(v.0.span.end, SourcePosition::into);
             Element {
+                span: Span { start: v.0.span.start, end },
                 name: v.0,
-                properties: v.1.unwrap_or_default(),
-                nodes: v.2.unwrap_or_default(),
+                properties: v.1.map(|v| v.0).unwrap_or_default(),
+                nodes: v.2.map(|v| v.0).unwrap_or_default(),
             }
         })
 }
@@ -183,17 +685,7 @@ fn body<'a, I>(input: &mut I) -> ParseResult<Vec<Node<'a>>, I>
                 .with(skip_many(skip_comment()))
                 .with(
                     try(char('}').map(|_| Flow::Break))
-                        .or(
-                            (
-                                position(),
-                                parse_string(),
-                                optional(properties()),
-                            ).map(|v| {
-                                Node::Text(v.1, SourcePosition::into(v.0), v.2.unwrap_or_default())
-                            })
-                            .or(parse_element().map(Node::Element))
-                            .map(|v| Flow::Continue(v))
-                        ),
+                        .or(parse_node().map(|v| Flow::Continue(v))),
                 )
                 .parse_stream(input)?;
         if let Flow::Continue(node) = ret {
@@ -205,6 +697,87 @@ fn body<'a, I>(input: &mut I) -> ParseResult<Vec<Node<'a>>, I>
     Ok((nodes, Consumed::Consumed(())))
 }
 
+fn parse_node<'a, I>() -> impl Parser<Input = I, Output = Node<'a>>
+    where
+        I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
+        <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
+{
+    (
+        position(),
+        parse_string(),
+        optional(properties()),
+    ).map(|v| {
+        Node::Text(v.1, SourcePosition::into(v.0), v.2.unwrap_or_default())
+    })
+    .or(
+        (
+            position(),
+            attempt(string("@slot")),
+            optional(
+                token('(')
+                    .skip(spaces())
+                    .with(ident())
+                    .skip(spaces())
+                    .skip(token(')'))
+            ),
+        ).map(|v| Node::Slot(v.2, SourcePosition::into(v.0)))
+    )
+    .or(
+        (
+            position(),
+            attempt(string("@if")),
+            spaces().with(token('(')),
+            spaces().with(optional(token('!'))),
+            spaces().with(ident()),
+            spaces().with(token(')')),
+            spaces().with(parser(body)),
+            optional(
+                attempt(spaces().with(skip_many(skip_comment())).with(string("@else")))
+                    .with(spaces())
+                    .with(parser(body))
+            ),
+        ).map(|v| Node::If(If {
+            name: v.4,
+            negated: v.3.is_some(),
+            nodes: v.6,
+            else_nodes: v.7.unwrap_or_default(),
+            position: SourcePosition::into(v.0),
+        }))
+    )
+    .or(
+        (
+            position(),
+            attempt(string("@for")),
+            spaces().with(token('(')),
+            spaces().with(ident()),
+            skip_many1(space()).with(string("in")),
+            skip_many1(space()).with(ident()),
+            spaces().with(token(')')),
+            spaces().with(parser(body)),
+        ).map(|v| Node::For(For {
+            item: v.3,
+            list: v.5,
+            nodes: v.7,
+            position: SourcePosition::into(v.0),
+        }))
+    )
+    .or(
+        (
+            parameter(),
+            optional(properties()),
+        ).map(|v| Node::Parameter(v.0, v.1.unwrap_or_default()))
+    )
+    .or(parse_element().map(Node::Element))
+}
+
+fn parameter<'a, I>() -> impl Parser<Input = I, Output = Ident<'a>>
+    where
+        I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
+        <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
+{
+    char('$').with(ident())
+}
+
 fn properties<'a, I>() -> impl Parser<Input = I, Output = FnvHashMap<Ident<'a>, ValueType<'a>>>
     where
         I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
@@ -237,20 +810,43 @@ fn value<'a, I>() -> impl Parser<Input = I, Output = ValueType<'a>>
     let boolean = parse_bool().map(|v| Value::Boolean(v));
     let float = parse_float().map(|v| Value::Float(v));
     let integer = parse_integer().map(|v| Value::Integer(v));
+    let color = parse_color().map(Value::Color);
 
-    let string = parse_string().map(|v| Value::String(v));
+    let string = parse_string().map(Value::String);
+    let tagged = (ident(), parse_string()).map(|v| Value::Tagged(v.0, v.1));
 
     (
         position(),
-        try(boolean).or(try(float)).or(try(integer)).or(string),
+        try(boolean).or(try(float)).or(try(integer)).or(string).or(color)
+            .or(parameter().map(Value::Parameter))
+            .or(attempt(tagged))
+            .or(ident().map(Value::Atom))
+            .or(parser(list).map(Value::List)),
+        position(),
     ).map(|v| {
             ValueType {
                 value: v.1,
                 position: SourcePosition::into(v.0),
+                span: Span::new(v.0, v.2),
             }
         })
 }
 
+/// Parses a list of values (`[a, b, c]`)
+fn list<'a, I>(input: &mut I) -> ParseResult<Vec<ValueType<'a>>, I>
+    where
+        I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
+        <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
+{
+    (
+        token('['),
+        spaces().with(sep_end_by(value().skip(spaces()), token(',').skip(spaces()))),
+        token(']'),
+    )
+        .map(|v| v.1)
+        .parse_stream(input)
+}
+
 #[cfg(test)]
 mod tests {
     use format_parse_error;
@@ -318,4 +914,401 @@ root(
             panic!("Expected error");
         }
     }
+
+    #[test]
+    fn test_template() {
+        let source = r#"
+// Templates can be defined before the root
+@template button(label, icon="button.png") {
+    button {
+        image(src=$icon)
+        $label
+    }
+}
+root {
+    button(label="Ok")
+}
+@template spacer {
+    spacer(size=5)
+}
+        "#;
+        let doc = Document::parse(source).unwrap();
+        match doc.nodes[0] {
+            Node::Element(ref e) => assert_eq!(e.name.name, "root"),
+            ref n => panic!("Unexpected node: {:?}", n),
+        }
+        assert_eq!(doc.templates.len(), 2);
+        let button = &doc.templates[0];
+        assert_eq!(button.name.name, "button");
+        assert_eq!(button.parameters.len(), 2);
+        assert!(button.parameters[0].1.is_none());
+        assert!(button.parameters[1].1.is_some());
+        match button.root.nodes[1] {
+            Node::Parameter(ref n, _) => assert_eq!(n.name, "label"),
+            ref n => panic!("Unexpected node: {:?}", n),
+        }
+        assert!(doc.templates[1].parameters.is_empty());
+
+        // Documents don't need any nodes
+        let doc = Document::parse(r#"
+@import "other.desc"
+@template a {
+    a
+}
+        "#).unwrap();
+        assert!(doc.nodes.is_empty());
+        assert_eq!(doc.imports[0].path, "other.desc");
+    }
+
+    #[test]
+    fn test_slot() {
+        let doc = Document::parse(r#"
+@template window {
+    window {
+        @slot
+        footer { @slot(footer) }
+    }
+}
+window {
+    "hello"
+}
+        "#).unwrap();
+        let root = &doc.templates[0].root;
+        match root.nodes[0] {
+            Node::Slot(None, _) => {},
+            ref n => panic!("Unexpected node: {:?}", n),
+        }
+        match root.nodes[1] {
+            Node::Element(ref e) => match e.nodes[0] {
+                Node::Slot(Some(ref n), _) => assert_eq!(n.name, "footer"),
+                ref n => panic!("Unexpected node: {:?}", n),
+            },
+            ref n => panic!("Unexpected node: {:?}", n),
+        }
+    }
+
+    #[test]
+    fn test_multiple_nodes() {
+        let doc = Document::parse(r#"
+// Overlays
+tooltip {}
+"Some text"(x=5)
+menu
+// Trailing comment
+        "#).unwrap();
+        assert_eq!(doc.nodes.len(), 3);
+        match doc.nodes[1] {
+            Node::Text(ref t, _, ref props) => {
+                assert_eq!(t, "Some text");
+                assert_eq!(props.len(), 1);
+            },
+            ref n => panic!("Unexpected node: {:?}", n),
+        }
+        assert_eq!(doc.nodes[2].position().line_number, 5);
+    }
+
+    #[test]
+    fn test_if_for() {
+        let doc = Document::parse(r#"
+menu {
+    @if(!logged_in) {
+        "Log in"
+    }
+    // Comments are allowed before else
+    @else {
+        "Log out"
+    }
+    @for(item in entries) {
+        entry { $item }
+    }
+}
+        "#).unwrap();
+        let nodes = match doc.nodes[0] {
+            Node::Element(ref e) => &e.nodes,
+            ref n => panic!("Unexpected node: {:?}", n),
+        };
+        match nodes[0] {
+            Node::If(ref v) => {
+                assert_eq!(v.name.name, "logged_in");
+                assert!(v.negated);
+                assert_eq!(v.nodes.len(), 1);
+                assert_eq!(v.else_nodes.len(), 1);
+            },
+            ref n => panic!("Unexpected node: {:?}", n),
+        }
+        match nodes[1] {
+            Node::For(ref v) => {
+                assert_eq!(v.item.name, "item");
+                assert_eq!(v.list.name, "entries");
+                assert_eq!(v.position.line_number, 10);
+            },
+            ref n => panic!("Unexpected node: {:?}", n),
+        }
+    }
+
+    #[test]
+    fn test_print() {
+        let source = r#"
+@import "widgets.desc"
+@template button(label, icon="a\"b.png", size=2.0) {
+    button(width=$size) {
+        image(src=$icon)
+        $label(size=12)
+        @slot(extra)
+        @slot
+    }
+}
+root(b=false, a=-1.5, c=5) {
+    "Hello \"world\"\n"(x=1)
+    empty
+    @if(!open) {
+        "Closed"
+    } @else {
+        panel
+    }
+    @for(item in list) {
+        $item
+    }
+}
+"Top level text"
+        "#;
+        let doc = Document::parse(source).unwrap();
+        let printed = doc.to_string();
+        assert_eq!(printed, r#"@import "widgets.desc"
+
+@template button(label, icon="a\"b.png", size=2.0) {
+    button(width=$size) {
+        image(src=$icon)
+        $label(size=12)
+        @slot(extra)
+        @slot
+    }
+}
+
+root(a=-1.5, b=false, c=5) {
+    "Hello \"world\"\n"(x=1)
+    empty
+    @if(!open) {
+        "Closed"
+    } @else {
+        panel
+    }
+    @for(item in list) {
+        $item
+    }
+}
+"Top level text"
+"#);
+        // Printing the parsed output must give the same text
+        let reparsed = Document::parse(&printed).unwrap();
+        assert_eq!(reparsed.to_string(), printed);
+    }
+
+    #[test]
+    fn test_spans() {
+        let source = r#"root(a=5) {
+    // Comment
+    image(src="a.png")
+    spacer
+}
+"#;
+        let doc = Document::parse(source).unwrap();
+        let span = |s: Span| (s.start.line_number, s.start.column, s.end.line_number, s.end.column);
+        let root = match doc.nodes[0] {
+            Node::Element(ref e) => e,
+            ref n => panic!("Unexpected node: {:?}", n),
+        };
+        assert_eq!(span(root.span), (1, 1, 5, 2));
+        assert_eq!(span(root.name.span), (1, 1, 1, 5));
+        let (_, a) = root.properties.iter().next().unwrap();
+        assert_eq!(span(a.span), (1, 8, 1, 9));
+        match root.nodes[0] {
+            Node::Element(ref e) => {
+                assert_eq!(span(e.span), (3, 5, 3, 23));
+                let (_, src) = e.properties.iter().next().unwrap();
+                assert_eq!(span(src.span), (3, 15, 3, 22));
+            },
+            ref n => panic!("Unexpected node: {:?}", n),
+        }
+        match root.nodes[1] {
+            Node::Element(ref e) => assert_eq!(span(e.span), (4, 5, 4, 11)),
+            ref n => panic!("Unexpected node: {:?}", n),
+        }
+        assert_eq!(doc.comments.len(), 1);
+        assert_eq!(span(doc.comments[0].span), (2, 5, 2, 15));
+    }
+
+    #[test]
+    fn test_recovering() {
+        let source = r#"
+panel(width=) {
+    "a"
+}
+"Text"
+@template broken( {
+    inner
+}
+label(text="}") {
+    "b"
+}
+"#;
+        let (doc, errors) = Document::parse_recovering(source);
+        assert_eq!(doc.nodes.len(), 2);
+        assert!(doc.templates.is_empty());
+        let lines: Vec<_> = errors.iter().map(|v| v.position.line_number).collect();
+        assert_eq!(lines, vec![2, 6]);
+    }
+
+    #[test]
+    fn test_into_owned() {
+        let source = String::from(r#"
+@template item(label) {
+    item { $label }
+}
+panel(title="Hello") {
+    "Some text"
+}
+"#);
+        let doc = Document::parse(&source).unwrap().into_owned();
+        let broken = String::from("panel {\n    label(=5)\n}");
+        let err = ::error_into_owned(Document::parse(&broken).unwrap_err());
+        drop(source);
+        drop(broken);
+
+        assert_eq!(doc.templates[0].name.name, "item");
+        match doc.nodes[0] {
+            Node::Element(ref e) => {
+                let title = e.properties.iter().find(|v| v.0.name == "title").unwrap();
+                match title.1.value {
+                    Value::String(ref v) => assert_eq!(v, "Hello"),
+                    ref v => panic!("Unexpected value: {:?}", v),
+                }
+            },
+            ref n => panic!("Unexpected node: {:?}", n),
+        }
+        assert_eq!(err.position.line, 2);
+    }
+
+    #[test]
+    fn test_literals() {
+        let doc = Document::parse(r#"
+panel(a=1e3, b=.5, c=-2.5E-1, d=0xff, e=-0b101, f=#ff8000, g=#00000080, h="\u{48}i")
+        "#).unwrap();
+        let e = match doc.nodes[0] {
+            Node::Element(ref e) => e,
+            ref n => panic!("Unexpected node: {:?}", n),
+        };
+        let prop = |name: &str| &e.properties.iter().find(|v| v.0.name == name).unwrap().1.value;
+        match (prop("a"), prop("b"), prop("c")) {
+            (&Value::Float(a), &Value::Float(b), &Value::Float(c)) => {
+                assert_eq!((a, b, c), (1000.0, 0.5, -0.25));
+            },
+            v => panic!("Unexpected values: {:?}", v),
+        }
+        match (prop("d"), prop("e")) {
+            (&Value::Integer(d), &Value::Integer(e)) => assert_eq!((d, e), (255, -5)),
+            v => panic!("Unexpected values: {:?}", v),
+        }
+        match (prop("f"), prop("g")) {
+            (&Value::Color(f), &Value::Color(g)) => {
+                assert_eq!(f, Color { r: 255, g: 128, b: 0, a: 255 });
+                assert_eq!(g.a, 128);
+                assert_eq!(f.to_string(), "#ff8000");
+                assert_eq!(g.to_string(), "#00000080");
+            },
+            v => panic!("Unexpected values: {:?}", v),
+        }
+        match *prop("h") {
+            Value::String(ref v) => assert_eq!(v, r"\u{48}i"),
+            ref v => panic!("Unexpected value: {:?}", v),
+        }
+
+        assert!(Document::parse("panel(a=#fff)").is_err());
+        assert!(Document::parse("panel(a=1-2)").is_err());
+        assert!(Document::parse(r#"panel(a="\u{d800}")"#).is_err());
+    }
+
+    #[test]
+    fn test_raw_strings() {
+        let source = r###"
+/* A block
+   comment */
+panel(script=r#"say("hi") // not a comment"#) {
+    r"C:\path" /* inline */
+    r##"ends with "#"##
+}
+        "###;
+        let doc = Document::parse(source).unwrap();
+        let e = match doc.nodes[0] {
+            Node::Element(ref e) => e,
+            ref n => panic!("Unexpected node: {:?}", n),
+        };
+        match e.properties.values().next().unwrap().value {
+            Value::String(ref v) => assert_eq!(v, r#"say(\"hi\") // not a comment"#),
+            ref v => panic!("Unexpected value: {:?}", v),
+        }
+        let text: Vec<_> = e.nodes.iter()
+            .map(|n| match *n {
+                Node::Text(ref t, ..) => &**t,
+                ref n => panic!("Unexpected node: {:?}", n),
+            })
+            .collect();
+        assert_eq!(text, vec![r#"C:\\path"#, r##"ends with \"#"##]);
+
+        let comments: Vec<_> = doc.comments.iter()
+            .map(|v| (&*v.text, v.block, v.span.end.line_number))
+            .collect();
+        assert_eq!(comments, vec![
+            (" A block\n   comment ", true, 3),
+            (" inline ", true, 5),
+        ]);
+
+        assert!(Document::parse("panel { /* unterminated }").is_err());
+        assert!(Document::parse(r##"panel { r#"unterminated" }"##).is_err());
+    }
+
+    #[test]
+    fn test_atoms() {
+        let doc = Document::parse("label(align=center, flag=true_ish, on=true)").unwrap();
+        let e = match doc.nodes[0] {
+            Node::Element(ref e) => e,
+            ref n => panic!("Unexpected node: {:?}", n),
+        };
+        let prop = |name: &str| &e.properties.iter().find(|v| v.0.name == name).unwrap().1.value;
+        match (prop("align"), prop("flag"), prop("on")) {
+            (Value::Atom(a), Value::Atom(b), Value::Boolean(true)) => {
+                assert_eq!((&*a.name, &*b.name), ("center", "true_ish"));
+            },
+            v => panic!("Unexpected values: {:?}", v),
+        }
+        assert_eq!(doc.to_string(), "label(align=center, flag=true_ish, on=true)\n");
+    }
+
+    #[test]
+    fn test_lists() {
+        let doc = Document::parse(r#"grid(columns=[1, "a", [b]], empty=[ ])"#).unwrap();
+        let e = match doc.nodes[0] {
+            Node::Element(ref e) => e,
+            ref n => panic!("Unexpected node: {:?}", n),
+        };
+        match e.properties.iter().find(|v| v.0.name == "columns").unwrap().1.value {
+            Value::List(ref v) => assert_eq!(v.len(), 3),
+            ref v => panic!("Unexpected value: {:?}", v),
+        }
+        assert_eq!(doc.to_string(), "grid(columns=[1, \"a\", [b]], empty=[])\n");
+    }
+
+    #[test]
+    fn test_tagged_literals() {
+        let doc = Document::parse(r##"image(src=img"ui/button", raw=r"a", both=[x"1"])"##).unwrap();
+        let e = match doc.nodes[0] {
+            Node::Element(ref e) => e,
+            ref n => panic!("Unexpected node: {:?}", n),
+        };
+        match e.properties.iter().find(|v| v.0.name == "src").unwrap().1.value {
+            Value::Tagged(ref t, ref v) => assert_eq!((&*t.name, &**v), ("img", "ui/button")),
+            ref v => panic!("Unexpected value: {:?}", v),
+        }
+        assert_eq!(doc.to_string(), "image(both=[x\"1\"], raw=\"a\", src=img\"ui/button\")\n");
+    }
 }
@@ -12,8 +12,19 @@ use combine::stream::state::{State, SourcePosition};
 use std::io::{self, Write};
 use std::hash::{Hash, Hasher};
 use std::fmt::{self, Display, Formatter};
+use std::borrow::Cow;
 
 pub type PError<'a> = ParseError<State<&'a str, SourcePosition>>;
+/// A parse error that doesn't borrow the source.
+///
+/// Created via [`error_into_owned`](fn.error_into_owned.html)
+pub type OwnedPError = Errors<char, String, SourcePosition>;
+
+/// Converts the parse error into one that doesn't borrow
+/// the source so that it can outlive it
+pub fn error_into_owned(err: PError) -> OwnedPError {
+    err.map_range(|v| v.to_owned())
+}
 
 pub use combine::easy::{Errors, Error, Info};
 
@@ -24,11 +35,25 @@ pub use combine::easy::{Errors, Error, Info};
 #[derive(Debug, Default, Clone)]
 pub struct Ident<'a> {
     /// The identifier's value/name
-    pub name: &'a str,
+    pub name: Cow<'a, str>,
     /// The position of the identifier within the source.
     ///
     /// Used for debugging.
     pub position: Position,
+    /// The source range of the identifier
+    pub span: Span,
+}
+
+impl <'a> Ident<'a> {
+    /// Converts the identifier into one that doesn't
+    /// borrow the source
+    pub fn into_owned(self) -> Ident<'static> {
+        Ident {
+            name: Cow::Owned(self.name.into_owned()),
+            position: self.position,
+            span: self.span,
+        }
+    }
 }
 
 impl <'a> PartialEq for Ident<'a> {
@@ -48,6 +73,47 @@ impl <'a> Hash for Ident<'a> {
     }
 }
 
+/// An `@import "path"` directive within a document
+#[derive(Debug, Clone)]
+pub struct Import<'a> {
+    /// The path of the imported file as written
+    /// in the document (still escaped)
+    pub path: Cow<'a, str>,
+    /// The position of the import within the source.
+    ///
+    /// Used for debugging.
+    pub position: Position,
+}
+
+impl <'a> Import<'a> {
+    /// Converts the import into one that doesn't
+    /// borrow the source
+    pub fn into_owned(self) -> Import<'static> {
+        Import {
+            path: Cow::Owned(self.path.into_owned()),
+            position: self.position,
+        }
+    }
+}
+
+/// A `//` or `/* */` comment within a document.
+///
+/// Comments don't affect the parsed document and are
+/// kept for tools that need to reproduce the source.
+#[derive(Debug, Clone)]
+pub struct Comment<'a> {
+    /// The text of the comment following the `//` or
+    /// between the `/*` and `*/`
+    pub text: Cow<'a, str>,
+    /// Whether this is a `/* */` comment
+    pub block: bool,
+    /// The position of the comment within the source.
+    pub position: Position,
+    /// The source range of the comment, not including
+    /// trailing whitespace
+    pub span: Span,
+}
+
 /// The position in the source file where the
 /// the ident/value/etc was defined.
 ///
@@ -65,6 +131,59 @@ pub struct Position {
     pub column: i32,
 }
 
+impl <'a> Comment<'a> {
+    /// Converts the comment into one that doesn't
+    /// borrow the source
+    pub fn into_owned(self) -> Comment<'static> {
+        Comment {
+            text: Cow::Owned(self.text.into_owned()),
+            block: self.block,
+            position: self.position,
+            span: self.span,
+        }
+    }
+}
+
+/// A range of the source file that an ident/value/etc
+/// was parsed from.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Span {
+    /// The position of the first character
+    pub start: Position,
+    /// The position just after the last character
+    pub end: Position,
+}
+
+impl Span {
+    pub(crate) fn new(start: SourcePosition, end: SourcePosition) -> Span {
+        Span {
+            start: start.into(),
+            end: end.into(),
+        }
+    }
+}
+
+/// A colour literal of the form `#rrggbb` or `#rrggbbaa`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    /// The alpha component. `255` when the literal
+    /// doesn't include one
+    pub a: u8,
+}
+
+impl Display for Color {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        write!(fmt, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)?;
+        if self.a != 255 {
+            write!(fmt, "{:02x}", self.a)?;
+        }
+        Ok(())
+    }
+}
+
 impl From<SourcePosition> for Position {
     fn from(v: SourcePosition) -> Position {
         Position {
@@ -145,16 +264,29 @@ where
 
 /// Formats a parsing error using [`format_error`].
 ///
+/// Accepts both borrowed (`PError`) and owned
+/// (`OwnedPError`) errors.
+///
 /// [`format_error`]: fn.format_error.html
-pub fn format_parse_error<'a, I, W>(
+pub fn format_parse_error<'a, I, W, R>(
     w: W,
     source: I,
-    err: ParseError<State<&'a str, SourcePosition>>,
+    err: Errors<char, R, SourcePosition>,
 ) -> Result<(), Box<::std::error::Error>>
 where
     W: Write,
     I: Iterator<Item = &'a str>,
+    R: Display,
 {
+    let position = err.position.into();
+    let (msg, label, token_len) = describe_parse_error(err)?;
+    format_error(w, source, position, token_len, &msg, &label)?;
+    Ok(())
+}
+
+/// Returns the message, label and length of the token
+/// for the error.
+fn describe_parse_error<R: Display>(err: Errors<char, R, SourcePosition>) -> Result<(String, String, usize), fmt::Error> {
     use combine::easy::{Error, Info};
     use std::fmt::Write;
     let mut msg = String::new();
@@ -184,7 +316,8 @@ where
                     Error::Message(ref m) => match *m {
                         Info::Owned(ref m) => msg.push_str(m),
                         Info::Borrowed(m) => msg.push_str(m),
-                        _ => unimplemented!(),
+                        Info::Token(t) => msg.push(t),
+                        Info::Range(ref r) => write!(&mut msg, "{}", r)?,
                     },
                     Error::Other(ref err) => write!(&mut msg, "{}", err)?,
                     Error::Expected(ref t) => write!(&mut msg, "Expected: {}", t)?,
@@ -215,7 +348,12 @@ where
                             write!(&mut msg, "{}", t)?;
                             write!(&mut label, "{}", t)?;
                         }
-                        _ => unimplemented!(),
+                        Info::Range(ref r) => {
+                            let r = r.to_string();
+                            msg.push_str(&r);
+                            label.push_str(&r);
+                            token_len = r.len();
+                        }
                     },
                     _ => unimplemented!(),
                 }
@@ -239,7 +377,9 @@ where
                         Info::Token(t) => {
                             write!(&mut msg, "{}", t)?;
                         }
-                        _ => unimplemented!(),
+                        Info::Range(ref r) => {
+                            write!(&mut msg, "{}", r)?;
+                        }
                     },
                     _ => {}
                 }
@@ -253,6 +393,133 @@ where
         }
     }
 
-    format_error(w, source, err.position.into(), token_len, &msg, &label)?;
-    Ok(())
+    Ok((msg, label, token_len))
+}
+
+/// An error found whilst parsing a document that
+/// could be recovered from.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    /// The position of the error within the source
+    pub position: Position,
+    /// The error message
+    pub message: String,
+    /// The label to place under the error
+    pub label: String,
+    /// The length of the token that caused the error
+    pub len: usize,
+}
+
+impl Diagnostic {
+    /// Creates a diagnostic from an error returned whilst
+    /// parsing
+    pub fn from_parse_error<R: Display>(err: Errors<char, R, SourcePosition>) -> Diagnostic {
+        let position = err.position.into();
+        let (message, label, len) = describe_parse_error(err)
+            .unwrap_or_else(|_| ("Unknown error occurred".into(), String::new(), 1));
+        Diagnostic {
+            position,
+            message,
+            label,
+            len,
+        }
+    }
+
+    /// Formats the diagnostic in a user friendly format using
+    /// [`format_error`]. `source` should be the lines of the
+    /// parsed document.
+    ///
+    /// [`format_error`]: fn.format_error.html
+    pub fn format<'a, I, W>(&self, w: W, source: I) -> io::Result<()>
+    where
+        W: Write,
+        I: Iterator<Item = &'a str>,
+    {
+        format_error(w, source, self.position, self.len, &self.message, &self.label)
+    }
 }
+
+impl Display for Diagnostic {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        write!(fmt, "{}: {}", self.position, self.message)
+    }
+}
+
+/// An error that occurred whilst loading a file
+#[derive(Debug, Clone)]
+pub struct FileError {
+    /// The name of the file the error occurred in
+    pub file: String,
+    /// The position of the error within the file if known
+    pub position: Option<Position>,
+    /// The error message
+    pub message: String,
+    /// The label to place under the error
+    pub label: String,
+    /// The length of the token that caused the error
+    pub len: usize,
+}
+
+impl FileError {
+    /// Creates an error without a position within the file
+    pub fn new<F, M>(file: F, message: M) -> FileError
+        where F: Into<String>,
+              M: Into<String>,
+    {
+        FileError {
+            file: file.into(),
+            position: None,
+            message: message.into(),
+            label: String::new(),
+            len: 1,
+        }
+    }
+
+    /// Creates an error from an error returned whilst parsing
+    /// or loading the named file.
+    pub fn from_parse_error<F, R>(file: F, err: Errors<char, R, SourcePosition>) -> FileError
+        where F: Into<String>,
+              R: Display,
+    {
+        let position = err.position.into();
+        let (message, label, len) = describe_parse_error(err)
+            .unwrap_or_else(|_| ("Unknown error occurred".into(), String::new(), 1));
+        FileError {
+            file: file.into(),
+            position: Some(position),
+            message,
+            label,
+            len,
+        }
+    }
+
+    /// Formats the error in a user friendly format using
+    /// [`format_error`]. `source` should be the lines of
+    /// the file the error occurred in.
+    ///
+    /// [`format_error`]: fn.format_error.html
+    pub fn format<'a, I, W>(&self, mut w: W, source: I) -> io::Result<()>
+    where
+        W: Write,
+        I: Iterator<Item = &'a str>,
+    {
+        write!(&mut w, "{}: ", self.file)?;
+        if let Some(pos) = self.position {
+            format_error(w, source, pos, self.len, &self.message, &self.label)
+        } else {
+            writeln!(&mut w, "error: {}", self.message)
+        }
+    }
+}
+
+impl Display for FileError {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        if let Some(pos) = self.position {
+            write!(fmt, "{}:{}: {}", self.file, pos, self.message)
+        } else {
+            write!(fmt, "{}: {}", self.file, self.message)
+        }
+    }
+}
+
+impl ::std::error::Error for FileError {}
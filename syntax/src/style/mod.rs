@@ -6,7 +6,9 @@
 //! The format is as follows:
 //!
 //! ```text,ignore
-//! // Comments (only single line)
+//! // Single line comments
+//! /* Block
+//!    comments */
 //!
 //! // Name of an element. Can be made up from any
 //! // letter, number or _
@@ -16,11 +18,58 @@
 //! }
 //! emoji(type="smile") {
 //!     image = "icons/smile.png",
+//!     // Identifiers that aren't variables are atoms if
+//!     // the style key allows them
+//!     align = center,
+//!     // Raw strings don't process escapes
+//!     tooltip = r#"A "smile""#,
+//!     // Tagged strings are parsed by the program
+//!     background = img"ui/smile",
+//!     // Lists can be indexed and measured with `len`
+//!     size = [16, 24, 32][level],
+//!     count = len(["a", "b"]),
 //! }
 //! panel > @text {
 //!     color = "#0050AA",
 //! }
+//! // Multiple selectors can share a single set of
+//! // styles by separating them with a comma
+//! button, toggle, tab > @text {
+//!     color = "#000000",
+//! }
+//! // Rules can be given an explicit priority to override
+//! // the normal ordering
+//! @priority(10) panel {
+//!     color = "#FF0000",
+//! }
+//! // Rules can be nested within other rules. This is the
+//! // same as writing `alert > title` and `alert > title > @text`
+//! alert {
+//!     title {
+//!         layout = "lined",
+//!         @text {
+//!             font_size = 24,
+//!         }
+//!     }
+//! }
+//! // Sets of styles can be shared between rules with mixins.
+//! // Styles in the including rule take precedence over the
+//! // mixin's.
+//! @mixin panel_base {
+//!     layout = "rows",
+//!     width = 300,
+//! }
+//! options_panel {
+//!     @include panel_base,
+//!     width = 400,
+//! }
+//! // Other files can be imported. How the path is resolved
+//! // is decided by the program loading the styles.
+//! @import "common.style"
 //! ```
+//!
+//! Parsed documents can be printed back out in a canonical
+//! format via `Display`. Nested rules keep their nesting.
 
 use fnv::FnvHashMap;
 use common::*;
@@ -31,14 +80,23 @@ use combine::error::*;
 use combine::Stream;
 use combine::easy::{ParseError,};
 use combine::stream::state::{State, SourcePosition};
-use super::{Ident, Position};
-use std::fmt::Debug;
+use combine::stream::StreamErrorFor;
+use super::{Color, Comment, Diagnostic, Ident, Import, Position, Span};
+use std::fmt::{self, Debug, Display, Formatter};
+use std::borrow::Cow;
 
 /// A UI style document
 #[derive(Debug)]
 pub struct Document<'a> {
     /// A list of rules in this document
     pub rules: Vec<Rule<'a>>,
+    /// A list of mixins defined in this document
+    pub mixins: Vec<Mixin<'a>>,
+    /// A list of files imported by this document
+    pub imports: Vec<Import<'a>>,
+    /// The comments within this document in the order
+    /// they appear
+    pub comments: Vec<Comment<'a>>,
 }
 
 impl <'a> Document<'a> {
@@ -61,15 +119,148 @@ impl <'a> Document<'a> {
     ///
     /// [`format_parse_error`]: ../fn.format_parse_error.html
     pub fn parse(source: &str) -> Result<Document, ParseError<State<&str, SourcePosition>>> {
-        let (doc, _) = parse_document().easy_parse(State::new(source))?;
+        let (mut doc, _) = parse_document().easy_parse(State::new(source))?;
+        doc.comments = collect_comments(source);
         Ok(doc)
     }
+
+    /// Parses the given string as a document, skipping over
+    /// rules, mixins and imports that contain syntax errors
+    /// instead of failing.
+    ///
+    /// Returns the document made up of the items that parsed
+    /// along with an error for every skipped item.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use fungui_syntax::style::Document;
+    /// let (doc, errors) = Document::parse_recovering(r##"
+    /// panel {
+    ///     width = 5 +,
+    /// }
+    /// button {
+    ///     width = 5,
+    /// }
+    /// "##);
+    /// assert_eq!(doc.rules.len(), 1);
+    /// assert_eq!(errors.len(), 1);
+    /// ```
+    pub fn parse_recovering(source: &'a str) -> (Document<'a>, Vec<Diagnostic>) {
+        let (items, diagnostics) = parse_items_recovering(source, parse_item);
+        let mut doc = Document::from_items(items);
+        doc.comments = collect_comments(source);
+        (doc, diagnostics)
+    }
+
+    fn from_items(items: Vec<Item<'a>>) -> Document<'a> {
+        let mut rules = Vec::with_capacity(items.len());
+        let mut mixins = Vec::new();
+        let mut imports = Vec::new();
+        for item in items {
+            match item {
+                Item::Rule(block) => rules.push(block.into_rule(0)),
+                Item::Mixin(mixin) => mixins.push(mixin),
+                Item::Import(import) => imports.push(import),
+            }
+        }
+        Document { rules, mixins, imports, comments: Vec::new() }
+    }
 }
 
+/// A set of styles and the selectors they apply to
 #[derive(Debug, Clone)]
 pub struct Rule<'a> {
-    pub matchers: Vec<(Matcher<'a>, FnvHashMap<Ident<'a>, ValueType<'a>>)>,
+    /// The selectors that share this rule's styles
+    ///
+    /// There is always at least one. The selectors of
+    /// nested rules are relative to their parent's.
+    pub selectors: Vec<Selector<'a>>,
+    /// The styles to apply to any matching node
+    pub styles: FnvHashMap<Ident<'a>, ExprType<'a>>,
+    /// The explicit priority of this rule set via
+    /// `@priority(n)`.
+    ///
+    /// Defaults to 0 or the parent's priority for
+    /// nested rules.
+    pub priority: i32,
+    /// The mixins included into this rule via `@include`
+    /// in the order they were included.
+    pub includes: Vec<Ident<'a>>,
+    /// The rules nested within this one.
+    ///
+    /// See [`flatten`](#method.flatten)
+    pub nested: Vec<Rule<'a>>,
+    /// The position of the rule within the source.
+    pub position: Position,
+    /// The source range of the rule including any
+    /// nested rules.
+    pub span: Span,
+}
+
+impl <'a> Rule<'a> {
+    /// Flattens this rule and any nested rules into `out`.
+    ///
+    /// Nested rules have their selectors prefixed with every
+    /// selector of their parent. None of the rules added to
+    /// `out` have nested rules.
+    pub fn flatten(self, out: &mut Vec<Rule<'a>>) {
+        self.flatten_into(&[], out)
+    }
+
+    fn flatten_into(self, parents: &[Selector<'a>], out: &mut Vec<Rule<'a>>) {
+        let selectors = if parents.is_empty() {
+            self.selectors
+        } else {
+            let mut selectors = Vec::with_capacity(parents.len() * self.selectors.len());
+            for parent in parents {
+                for selector in &self.selectors {
+                    let mut matchers = parent.matchers.clone();
+                    matchers.extend(selector.matchers.iter().cloned());
+                    selectors.push(Selector { matchers });
+                }
+            }
+            selectors
+        };
+        let nested = self.nested;
+        // Rules that only exist to hold nested rules don't
+        // need to be kept
+        if !self.styles.is_empty() || !self.includes.is_empty() || nested.is_empty() {
+            out.push(Rule {
+                selectors: selectors.clone(),
+                styles: self.styles,
+                priority: self.priority,
+                includes: self.includes,
+                nested: Vec::new(),
+                position: self.position,
+                span: self.span,
+            });
+        }
+        for rule in nested {
+            rule.flatten_into(&selectors, out);
+        }
+    }
+}
+
+/// A named set of styles that can be included into
+/// rules
+#[derive(Debug, Clone)]
+pub struct Mixin<'a> {
+    /// The name of this mixin
+    pub name: Ident<'a>,
+    /// The styles that this mixin provides
     pub styles: FnvHashMap<Ident<'a>, ExprType<'a>>,
+    /// Other mixins included into this one via `@include`
+    /// in the order they were included.
+    pub includes: Vec<Ident<'a>>,
+}
+
+/// A chain of matchers used to select nodes
+#[derive(Debug, Clone)]
+pub struct Selector<'a> {
+    /// The matchers in this selector from the outermost
+    /// node to the node being styled
+    pub matchers: Vec<(Matcher<'a>, FnvHashMap<Ident<'a>, ValueType<'a>>)>,
 }
 
 #[derive(Debug, Clone)]
@@ -88,6 +279,8 @@ pub enum Matcher<'a> {
 pub struct Element<'a> {
     /// The name of this element
     pub name: Ident<'a>,
+    /// The source range of the element
+    pub span: Span,
 }
 
 /// Contains a value and debugging information
@@ -100,6 +293,8 @@ pub struct ValueType<'a> {
     ///
     /// Used for debugging.
     pub position: Position,
+    /// The source range of the value
+    pub span: Span,
 }
 
 /// A parsed value for a property
@@ -112,9 +307,17 @@ pub enum Value<'a> {
     /// A 64 bit float (of the form `0.0`)
     Float(f64),
     /// A quoted string
-    String(&'a str),
-    /// A variable name
+    String(Cow<'a, str>),
+    /// A colour (`#rrggbb` or `#rrggbbaa`)
+    Color(Color),
+    /// A variable name or a bare identifier (atom).
+    ///
+    /// Identifiers that don't name a variable are treated
+    /// as atoms if the style key allows them.
     Variable(Ident<'a>),
+    /// A string tagged with a name (`img"ui/button"`)
+    /// that is parsed by the program
+    Tagged(Ident<'a>, Cow<'a, str>),
 }
 
 #[derive(Debug, Clone)]
@@ -123,8 +326,11 @@ pub struct ExprType<'a> {
     pub expr: Expr<'a>,
     /// The position of the value within the source.
     ///
+    /// For operators this is the position of the operator.
     /// Used for debugging.
     pub position: Position,
+    /// The source range of the whole expression
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -154,6 +360,443 @@ pub enum Expr<'a> {
     FloatToInt(Box<ExprType<'a>>),
 
     Call(Ident<'a>, Vec<ExprType<'a>>),
+
+    /// A list of values (`[a, b, c]`)
+    List(Vec<ExprType<'a>>),
+    /// An element of a list (`list[index]`)
+    Index(Box<ExprType<'a>>, Box<ExprType<'a>>),
+
+    /// A property on the parent element (`parent.name`)
+    ParentProperty(Ident<'a>),
+    /// A property on the nearest ancestor element with the
+    /// given name (`ancestor(element).name`)
+    AncestorProperty(Ident<'a>, Ident<'a>),
+}
+
+impl <'a> Document<'a> {
+    /// Converts the document into one that doesn't borrow
+    /// the source so that it can be cached or sent to
+    /// other threads.
+    pub fn into_owned(self) -> Document<'static> {
+        Document {
+            rules: self.rules.into_iter().map(Rule::into_owned).collect(),
+            mixins: self.mixins.into_iter().map(Mixin::into_owned).collect(),
+            imports: self.imports.into_iter().map(Import::into_owned).collect(),
+            comments: self.comments.into_iter().map(Comment::into_owned).collect(),
+        }
+    }
+}
+
+impl <'a> Rule<'a> {
+    /// Converts the rule into one that doesn't borrow
+    /// the source
+    pub fn into_owned(self) -> Rule<'static> {
+        Rule {
+            selectors: self.selectors.into_iter().map(Selector::into_owned).collect(),
+            styles: owned_styles(self.styles),
+            priority: self.priority,
+            includes: self.includes.into_iter().map(Ident::into_owned).collect(),
+            nested: self.nested.into_iter().map(Rule::into_owned).collect(),
+            position: self.position,
+            span: self.span,
+        }
+    }
+}
+
+impl <'a> Mixin<'a> {
+    /// Converts the mixin into one that doesn't borrow
+    /// the source
+    pub fn into_owned(self) -> Mixin<'static> {
+        Mixin {
+            name: self.name.into_owned(),
+            styles: owned_styles(self.styles),
+            includes: self.includes.into_iter().map(Ident::into_owned).collect(),
+        }
+    }
+}
+
+impl <'a> Selector<'a> {
+    /// Converts the selector into one that doesn't borrow
+    /// the source
+    pub fn into_owned(self) -> Selector<'static> {
+        Selector {
+            matchers: self.matchers.into_iter()
+                .map(|(m, props)| (
+                    match m {
+                        Matcher::Element(e) => Matcher::Element(Element {
+                            name: e.name.into_owned(),
+                            span: e.span,
+                        }),
+                        Matcher::Text => Matcher::Text,
+                    },
+                    props.into_iter()
+                        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+                        .collect(),
+                ))
+                .collect(),
+        }
+    }
+}
+
+fn owned_styles(styles: FnvHashMap<Ident, ExprType>) -> FnvHashMap<Ident<'static>, ExprType<'static>> {
+    styles.into_iter()
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect()
+}
+
+impl <'a> ValueType<'a> {
+    /// Converts the value into one that doesn't borrow
+    /// the source
+    pub fn into_owned(self) -> ValueType<'static> {
+        ValueType {
+            value: self.value.into_owned(),
+            position: self.position,
+            span: self.span,
+        }
+    }
+}
+
+impl <'a> Value<'a> {
+    /// Converts the value into one that doesn't borrow
+    /// the source
+    pub fn into_owned(self) -> Value<'static> {
+        match self {
+            Value::Boolean(v) => Value::Boolean(v),
+            Value::Integer(v) => Value::Integer(v),
+            Value::Float(v) => Value::Float(v),
+            Value::Color(v) => Value::Color(v),
+            Value::String(v) => Value::String(Cow::Owned(v.into_owned())),
+            Value::Variable(v) => Value::Variable(v.into_owned()),
+            Value::Tagged(t, v) => Value::Tagged(t.into_owned(), Cow::Owned(v.into_owned())),
+        }
+    }
+}
+
+impl <'a> ExprType<'a> {
+    /// Converts the expression into one that doesn't borrow
+    /// the source
+    pub fn into_owned(self) -> ExprType<'static> {
+        fn b(e: ExprType) -> Box<ExprType<'static>> {
+            Box::new(e.into_owned())
+        }
+        ExprType {
+            expr: match self.expr {
+                Expr::Value(v) => Expr::Value(v.into_owned()),
+                Expr::Neg(e) => Expr::Neg(b(*e)),
+                Expr::Not(e) => Expr::Not(b(*e)),
+                Expr::And(l, r) => Expr::And(b(*l), b(*r)),
+                Expr::Or(l, r) => Expr::Or(b(*l), b(*r)),
+                Expr::Xor(l, r) => Expr::Xor(b(*l), b(*r)),
+                Expr::Add(l, r) => Expr::Add(b(*l), b(*r)),
+                Expr::Sub(l, r) => Expr::Sub(b(*l), b(*r)),
+                Expr::Mul(l, r) => Expr::Mul(b(*l), b(*r)),
+                Expr::Div(l, r) => Expr::Div(b(*l), b(*r)),
+                Expr::Rem(l, r) => Expr::Rem(b(*l), b(*r)),
+                Expr::Equal(l, r) => Expr::Equal(b(*l), b(*r)),
+                Expr::NotEqual(l, r) => Expr::NotEqual(b(*l), b(*r)),
+                Expr::LessEqual(l, r) => Expr::LessEqual(b(*l), b(*r)),
+                Expr::GreaterEqual(l, r) => Expr::GreaterEqual(b(*l), b(*r)),
+                Expr::Less(l, r) => Expr::Less(b(*l), b(*r)),
+                Expr::Greater(l, r) => Expr::Greater(b(*l), b(*r)),
+                Expr::IntToFloat(e) => Expr::IntToFloat(b(*e)),
+                Expr::FloatToInt(e) => Expr::FloatToInt(b(*e)),
+                Expr::Call(n, args) => Expr::Call(
+                    n.into_owned(),
+                    args.into_iter().map(ExprType::into_owned).collect(),
+                ),
+                Expr::List(v) => Expr::List(v.into_iter().map(ExprType::into_owned).collect()),
+                Expr::Index(l, i) => Expr::Index(b(*l), b(*i)),
+                Expr::ParentProperty(n) => Expr::ParentProperty(n.into_owned()),
+                Expr::AncestorProperty(e, n) => Expr::AncestorProperty(e.into_owned(), n.into_owned()),
+            },
+            position: self.position,
+            span: self.span,
+        }
+    }
+}
+
+/// Prints the document in a canonical format.
+///
+/// Items are printed in the order they appear in the source
+/// with comments placed before the item or style that follows
+/// them.
+impl <'a> Display for Document<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        enum Item<'b, 'a: 'b> {
+            Import(&'b Import<'a>),
+            Mixin(&'b Mixin<'a>),
+            Rule(&'b Rule<'a>),
+        }
+        let mut items: Vec<_> = self.imports.iter().map(|v| (v.position, Item::Import(v)))
+            .chain(self.mixins.iter().map(|v| (v.name.position, Item::Mixin(v))))
+            .chain(self.rules.iter().map(|v| (v.position, Item::Rule(v))))
+            .collect();
+        items.sort_by_key(|v| position_key(v.0));
+        let has_items = !items.is_empty();
+
+        let mut printer = Printer {
+            f,
+            comments: &self.comments,
+        };
+        let mut last_import = false;
+        for (idx, (position, item)) in items.into_iter().enumerate() {
+            let is_import = matches!(item, Item::Import(_));
+            // Imports are grouped together, everything else is
+            // separated by a blank line
+            if idx != 0 && !(is_import && last_import) {
+                writeln!(printer.f)?;
+            }
+            last_import = is_import;
+            printer.comments_before(position, 0)?;
+            match item {
+                Item::Import(v) => writeln!(printer.f, "@import \"{}\"", v.path)?,
+                Item::Mixin(v) => {
+                    write!(printer.f, "@mixin {} ", v.name.name)?;
+                    printer.body(&v.includes, &v.styles, &[], 0, 0)?;
+                },
+                Item::Rule(v) => printer.rule(v, 0, 0)?,
+            }
+        }
+        if has_items && !printer.comments.is_empty() {
+            writeln!(printer.f)?;
+        }
+        printer.comments_before(Position { line_number: i32::MAX, column: 0 }, 0)
+    }
+}
+
+fn position_key(p: Position) -> (i32, i32) {
+    (p.line_number, p.column)
+}
+
+/// Prints comments in order as the document is printed
+struct Printer<'f, 'w: 'f, 'b, 'a: 'b> {
+    f: &'f mut Formatter<'w>,
+    comments: &'b [Comment<'a>],
+}
+
+impl <'f, 'w, 'b, 'a> Printer<'f, 'w, 'b, 'a> {
+    /// Prints the remaining comments that appear before the
+    /// position
+    fn comments_before(&mut self, position: Position, indent: usize) -> fmt::Result {
+        while let Some(comment) = self.comments.first() {
+            if position_key(comment.position) >= position_key(position) {
+                break;
+            }
+            self.indent(indent)?;
+            if comment.block {
+                writeln!(self.f, "/*{}*/", comment.text)?;
+            } else {
+                writeln!(self.f, "//{}", comment.text)?;
+            }
+            self.comments = &self.comments[1..];
+        }
+        Ok(())
+    }
+
+    fn indent(&mut self, indent: usize) -> fmt::Result {
+        for _ in 0 .. indent {
+            write!(self.f, "    ")?;
+        }
+        Ok(())
+    }
+
+    /// Prints the rule. The priority is only printed if it
+    /// differs from the one the rule would inherit
+    fn rule(&mut self, rule: &Rule, parent_priority: i32, indent: usize) -> fmt::Result {
+        if rule.priority != parent_priority {
+            write!(self.f, "@priority({}) ", rule.priority)?;
+        }
+        for (idx, selector) in rule.selectors.iter().enumerate() {
+            if idx != 0 {
+                write!(self.f, ", ")?;
+            }
+            write!(self.f, "{}", selector)?;
+        }
+        write!(self.f, " ")?;
+        self.body(&rule.includes, &rule.styles, &rule.nested, rule.priority, indent)
+    }
+
+    fn body(
+        &mut self,
+        includes: &[Ident], styles: &FnvHashMap<Ident, ExprType>, nested: &[Rule],
+        priority: i32, indent: usize,
+    ) -> fmt::Result {
+        enum Entry<'b, 'a: 'b> {
+            Include(&'b Ident<'a>),
+            Style(&'b Ident<'a>, &'b ExprType<'a>),
+            Nested(&'b Rule<'a>),
+        }
+        let mut entries: Vec<_> = includes.iter().map(|v| (v.position, Entry::Include(v)))
+            .chain(styles.iter().map(|(k, v)| (k.position, Entry::Style(k, v))))
+            .chain(nested.iter().map(|v| (v.position, Entry::Nested(v))))
+            .collect();
+        entries.sort_by_key(|v| position_key(v.0));
+        writeln!(self.f, "{{")?;
+        for (position, entry) in entries {
+            self.comments_before(position, indent + 1)?;
+            self.indent(indent + 1)?;
+            match entry {
+                Entry::Include(name) => writeln!(self.f, "@include {},", name.name)?,
+                Entry::Style(name, expr) => writeln!(self.f, "{} = {},", name.name, expr)?,
+                Entry::Nested(rule) => self.rule(rule, priority, indent + 1)?,
+            }
+        }
+        self.indent(indent)?;
+        writeln!(self.f, "}}")
+    }
+}
+
+impl <'a> Display for Selector<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for (idx, (matcher, props)) in self.matchers.iter().enumerate() {
+            if idx != 0 {
+                write!(f, " > ")?;
+            }
+            match *matcher {
+                Matcher::Element(ref e) => write!(f, "{}", e.name.name)?,
+                Matcher::Text => write!(f, "@text")?,
+            }
+            if !props.is_empty() {
+                let mut props: Vec<_> = props.iter().collect();
+                props.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+                write!(f, "(")?;
+                for (idx, (name, value)) in props.into_iter().enumerate() {
+                    if idx != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}={}", name.name, value.value)?;
+                }
+                write!(f, ")")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl <'a> Display for Value<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Value::Boolean(v) => write!(f, "{}", v),
+            Value::Integer(v) => write!(f, "{}", v),
+            Value::Float(v) => write_float(f, v),
+            Value::Color(v) => write!(f, "{}", v),
+            Value::String(ref v) => write!(f, "\"{}\"", v),
+            Value::Variable(ref v) => write!(f, "{}", v.name),
+            Value::Tagged(ref t, ref v) => write!(f, "{}\"{}\"", t.name, v),
+        }
+    }
+}
+
+impl <'a> Display for ExprType<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        Display::fmt(&self.expr, f)
+    }
+}
+
+impl <'a> Expr<'a> {
+    /// How tightly the expression binds, higher binds
+    /// tighter.
+    fn precedence(&self) -> u8 {
+        match *self {
+            Expr::Equal(..) | Expr::NotEqual(..)
+            | Expr::LessEqual(..) | Expr::GreaterEqual(..)
+            | Expr::Less(..) | Expr::Greater(..) => 1,
+            Expr::And(..) | Expr::Or(..) | Expr::Xor(..) => 2,
+            Expr::Add(..) | Expr::Sub(..) => 3,
+            Expr::Mul(..) | Expr::Div(..) | Expr::Rem(..) => 4,
+            Expr::Neg(_) | Expr::Not(_) => 5,
+            _ => 6,
+        }
+    }
+}
+
+/// Prints a binary operator, adding parentheses only where
+/// they are needed to keep the same meaning.
+///
+/// Unary operators take the whole expression that follows them
+/// so they are always wrapped when used as an operand.
+fn write_binary(f: &mut Formatter, l: &ExprType, op: &str, r: &ExprType, precedence: u8) -> fmt::Result {
+    let lp = l.expr.precedence();
+    if lp < precedence || lp == 5 {
+        write!(f, "({})", l)?;
+    } else {
+        write!(f, "{}", l)?;
+    }
+    write!(f, " {} ", op)?;
+    let rp = r.expr.precedence();
+    if rp <= precedence || rp == 5 {
+        write!(f, "({})", r)
+    } else {
+        write!(f, "{}", r)
+    }
+}
+
+fn write_list(f: &mut Formatter, v: &[ExprType]) -> fmt::Result {
+    for (idx, e) in v.iter().enumerate() {
+        if idx != 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", e)?;
+    }
+    Ok(())
+}
+
+fn write_unary(f: &mut Formatter, op: &str, e: &ExprType) -> fmt::Result {
+    if e.expr.precedence() == 6 {
+        write!(f, "{}{}", op, e)
+    } else {
+        write!(f, "{}({})", op, e)
+    }
+}
+
+impl <'a> Display for Expr<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let p = self.precedence();
+        match *self {
+            Expr::Value(ref v) => write!(f, "{}", v),
+            Expr::Neg(ref e) => write_unary(f, "-", e),
+            Expr::Not(ref e) => write_unary(f, "!", e),
+
+            Expr::And(ref l, ref r) => write_binary(f, l, "&&", r, p),
+            Expr::Or(ref l, ref r) => write_binary(f, l, "||", r, p),
+            Expr::Xor(ref l, ref r) => write_binary(f, l, "^", r, p),
+
+            Expr::Add(ref l, ref r) => write_binary(f, l, "+", r, p),
+            Expr::Sub(ref l, ref r) => write_binary(f, l, "-", r, p),
+            Expr::Mul(ref l, ref r) => write_binary(f, l, "*", r, p),
+            Expr::Div(ref l, ref r) => write_binary(f, l, "/", r, p),
+            Expr::Rem(ref l, ref r) => write_binary(f, l, "%", r, p),
+
+            Expr::Equal(ref l, ref r) => write_binary(f, l, "==", r, p),
+            Expr::NotEqual(ref l, ref r) => write_binary(f, l, "!=", r, p),
+            Expr::LessEqual(ref l, ref r) => write_binary(f, l, "<=", r, p),
+            Expr::GreaterEqual(ref l, ref r) => write_binary(f, l, ">=", r, p),
+            Expr::Less(ref l, ref r) => write_binary(f, l, "<", r, p),
+            Expr::Greater(ref l, ref r) => write_binary(f, l, ">", r, p),
+
+            Expr::IntToFloat(ref e) => write!(f, "float({})", e),
+            Expr::FloatToInt(ref e) => write!(f, "int({})", e),
+
+            Expr::Call(ref name, ref args) => {
+                write!(f, "{}(", name.name)?;
+                write_list(f, args)?;
+                write!(f, ")")
+            },
+            Expr::List(ref v) => {
+                write!(f, "[")?;
+                write_list(f, v)?;
+                write!(f, "]")
+            },
+            Expr::Index(ref l, ref i) => if l.expr.precedence() < 6 {
+                write!(f, "({})[{}]", l, i)
+            } else {
+                write!(f, "{}[{}]", l, i)
+            },
+
+            Expr::ParentProperty(ref name) => write!(f, "parent.{}", name.name),
+            Expr::AncestorProperty(ref element, ref name) => write!(f, "ancestor({}).{}", element.name, name.name),
+        }
+    }
 }
 
 fn parse_document<'a, I>() -> impl Parser<Input = I, Output = Document<'a>>
@@ -161,13 +804,100 @@ fn parse_document<'a, I>() -> impl Parser<Input = I, Output = Document<'a>>
         I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
         <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
 {
-    let rule = (parse_rule(), spaces()).map(|v| v.0);
+    let item = (
+        parse_item(),
+        spaces(),
+        skip_many(skip_comment()),
+    ).map(|v| v.0);
     spaces()
-        .with(many1(rule))
-        .map(|e| Document { rules: e })
+        .with(skip_many(skip_comment()))
+        .with(many1(item))
+        .map(Document::from_items)
+}
+
+/// A top level item within a document
+enum Item<'a> {
+    Rule(RuleBlock<'a>),
+    Mixin(Mixin<'a>),
+    Import(Import<'a>),
 }
 
-fn parse_rule<'a, I>() -> impl Parser<Input = I, Output = Rule<'a>>
+fn parse_item<'a, I>() -> impl Parser<Input = I, Output = Item<'a>>
+    where
+        I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
+        <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
+{
+    parse_import().map(Item::Import)
+        .or(parse_mixin().map(Item::Mixin))
+        .or(parse_rule().map(Item::Rule))
+}
+
+fn parse_mixin<'a, I>() -> impl Parser<Input = I, Output = Mixin<'a>>
+    where
+        I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
+        <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
+{
+    let comments = skip_many(skip_comment());
+
+    let mixin = (
+        attempt(string("@mixin")).skip(skip_many1(space())),
+        ident(),
+        spaces().with(parser(styles)
+            .and_then(|body: Body<'a>| if body.nested.is_empty() {
+                Ok(body)
+            } else {
+                Err(StreamErrorFor::<I>::message_static_message("Mixins can't contain nested rules"))
+            })),
+    );
+
+    attempt(spaces().with(comments).with(look_ahead(string("@mixin"))))
+        .with(mixin)
+        .map(|v| {
+            Mixin {
+                name: v.1,
+                styles: v.2.styles,
+                includes: v.2.includes,
+            }
+        })
+}
+
+/// A rule as parsed, possibly containing nested rules
+struct RuleBlock<'a> {
+    position: Position,
+    span: Span,
+    priority: Option<i32>,
+    selectors: Vec<Selector<'a>>,
+    body: Body<'a>,
+}
+
+/// The contents of a rule or mixin
+struct Body<'a> {
+    styles: FnvHashMap<Ident<'a>, ExprType<'a>>,
+    includes: Vec<Ident<'a>>,
+    nested: Vec<RuleBlock<'a>>,
+}
+
+impl <'a> RuleBlock<'a> {
+    /// Converts the parsed rule into a `Rule`. Nested rules
+    /// inherit their parent's priority unless they have
+    /// their own.
+    fn into_rule(self, parent_priority: i32) -> Rule<'a> {
+        let priority = self.priority.unwrap_or(parent_priority);
+        Rule {
+            selectors: self.selectors,
+            styles: self.body.styles,
+            priority,
+            includes: self.body.includes,
+            nested: self.body.nested.into_iter()
+                .map(|v| v.into_rule(priority))
+                .collect(),
+            position: self.position,
+            span: self.span,
+        }
+    }
+}
+
+fn parse_rule<'a, I>() -> impl Parser<Input = I, Output = RuleBlock<'a>>
     where
         I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
         <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
@@ -175,23 +905,40 @@ fn parse_rule<'a, I>() -> impl Parser<Input = I, Output = Rule<'a>>
     let comments = skip_many(skip_comment());
 
     let matcher = (
-        try(spaces().with(string("@text").map(|_| Matcher::Text)))
+        attempt(spaces().with(string("@text").map(|_| Matcher::Text)))
             .or(parse_element().map(|v| Matcher::Element(v))),
         optional(properties()).map(|v| v.unwrap_or_default()),
     );
 
+    let selector = sep_by1(attempt(matcher), attempt(spaces().with(token('>'))))
+        .map(|v| Selector { matchers: v });
+
+    let priority = attempt(string("@priority"))
+        .skip(spaces())
+        .skip(token('('))
+        .skip(spaces())
+        .with(parse_integer())
+        .skip(spaces())
+        .skip(token(')'));
+
     let rule = (
-        sep_by1(try(matcher), try(spaces().with(token('>')))),
+        position(),
+        optional(priority),
+        sep_by1(selector, attempt(spaces().with(token(',')))),
         spaces().with(parser(styles)),
+        position(),
     );
 
     spaces()
         .with(comments)
         .with(rule)
         .map(|v| {
-            Rule {
-                matchers: v.0,
-                styles: v.1,
+            RuleBlock {
+                position: SourcePosition::into(v.0),
+                span: Span::new(v.0, v.4),
+                priority: v.1,
+                selectors: v.2,
+                body: v.3,
             }
         })
 }
@@ -203,46 +950,62 @@ fn parse_element<'a, I>() -> impl Parser<Input = I, Output = Element<'a>>
 {
     let comments = skip_many(skip_comment());
 
-    let element = ident().skip(look_ahead(char('{').or(char('(')).or(space()).map(|_| ())));
+    let element = ident().skip(look_ahead(char('{').or(char('(')).or(char(',')).or(space()).map(|_| ())));
 
     spaces()
         .with(comments)
         .with(element)
-        .map(|v| Element { name: v })
+        .map(|v| Element { span: v.span, name: v })
 }
 
-fn styles<'a, I>(input: &mut I) -> ParseResult<FnvHashMap<Ident<'a>, ExprType<'a>>, I>
+fn styles<'a, I>(input: &mut I) -> ParseResult<Body<'a>, I>
     where
         I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
         <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
 {
     let (_, _) = char('{').parse_stream(input)?;
 
-    enum Flow<T> {
-        Continue(T),
+    enum Flow<'a> {
+        Property(Ident<'a>, ExprType<'a>),
+        Include(Ident<'a>),
+        Nested(RuleBlock<'a>),
         Break,
     }
 
-    let mut styles = FnvHashMap::default();
+    let mut body = Body {
+        styles: FnvHashMap::default(),
+        includes: Vec::new(),
+        nested: Vec::new(),
+    };
     loop {
         let prop = (style_property(), optional(token(',')));
+        let include = (
+            attempt(string("@include")).skip(skip_many1(space())),
+            ident(),
+            spaces().with(optional(token(','))),
+        );
         let (ret, _) = spaces()
                 .with(skip_many(skip_comment()))
                 .with(
-                    try(char('}').map(|_| Flow::Break))
+                    attempt(char('}').map(|_| Flow::Break))
                         .or(
                             prop
-                            .map(|v| Flow::Continue(v.0))
-                        ),
+                            .map(|v| Flow::Property((v.0).0, (v.0).1))
+                        )
+                        .or(include.map(|v| Flow::Include(v.1)))
+                        .or(parse_rule().map(Flow::Nested)),
                 )
                 .parse_stream(input)?;
-        if let Flow::Continue(s) = ret {
-            styles.insert(s.0, s.1);
-        } else {
-            break;
+        match ret {
+            Flow::Property(key, expr) => {
+                body.styles.insert(key, expr);
+            }
+            Flow::Include(name) => body.includes.push(name),
+            Flow::Nested(rule) => body.nested.push(rule),
+            Flow::Break => break,
         }
     }
-    Ok((styles, Consumed::Consumed(())))
+    Ok((body, Consumed::Consumed(())))
 }
 
 fn style_property<'a, I>() -> impl Parser<Input = I, Output = (Ident<'a>, ExprType<'a>)>
@@ -250,11 +1013,16 @@ fn style_property<'a, I>() -> impl Parser<Input = I, Output = (Ident<'a>, ExprTy
         I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
         <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
 {
+    // Only the key is attempted so that the property can be
+    // told apart from a nested rule without hiding errors
+    // in the expression
     (
-        spaces().with(ident()),
-        spaces().with(token('=')),
+        attempt((
+            spaces().with(ident()),
+            spaces().with(token('=')),
+        )),
         spaces().with(parser(expr)),
-    ).map(|v| (v.0, v.2))
+    ).map(|v| ((v.0).0, v.1))
 }
 
 fn expr<'a, I>(input: &mut I) -> ParseResult<ExprType<'a>, I>
@@ -290,6 +1058,7 @@ fn expr<'a, I>(input: &mut I) -> ParseResult<ExprType<'a>, I>
             .parse_stream(input)?;
         current = ExprType {
             position: SourcePosition::into(op.0),
+            span: Span { start: current.span.start, end: other.span.end },
             expr: match op.1 {
                 "==" => Expr::Equal(Box::new(current), Box::new(other)),
                 "!=" => Expr::NotEqual(Box::new(current), Box::new(other)),
@@ -332,6 +1101,7 @@ fn bool_ops<'a, I>(input: &mut I) -> ParseResult<ExprType<'a>, I>
             .parse_stream(input)?;
         current = ExprType {
             position: SourcePosition::into(op.0),
+            span: Span { start: current.span.start, end: other.span.end },
             expr: match op.1 {
                 "&&" => Expr::And(Box::new(current), Box::new(other)),
                 "||" => Expr::Or(Box::new(current), Box::new(other)),
@@ -368,6 +1138,7 @@ fn term1<'a, I>(input: &mut I) -> ParseResult<ExprType<'a>, I>
             .parse_stream(input)?;
         current = ExprType {
             position: SourcePosition::into(op.0),
+            span: Span { start: current.span.start, end: other.span.end },
             expr: match op.1 {
                 '+' => Expr::Add(Box::new(current), Box::new(other)),
                 '-' => Expr::Sub(Box::new(current), Box::new(other)),
@@ -403,6 +1174,7 @@ fn term2<'a, I>(input: &mut I) -> ParseResult<ExprType<'a>, I>
             .parse_stream(input)?;
         current = ExprType {
             position: SourcePosition::into(op.0),
+            span: Span { start: current.span.start, end: other.span.end },
             expr: match op.1 {
                 '*' => Expr::Mul(Box::new(current), Box::new(other)),
                 '/' => Expr::Div(Box::new(current), Box::new(other)),
@@ -452,6 +1224,20 @@ fn factor<'a, I>() -> impl Parser<Input = I, Output = ExprType<'a>>
         .skip(skip_spaces())
         .skip(char(')'));
 
+    let parent_property = string("parent")
+        .skip(char('.'))
+        .with(ident())
+        .map(Expr::ParentProperty);
+    let ancestor_property = (
+        string("ancestor")
+            .skip(char('('))
+            .skip(skip_spaces())
+            .with(ident())
+            .skip(skip_spaces())
+            .skip(char(')')),
+        char('.').with(ident()),
+    ).map(|v| Expr::AncestorProperty(v.0, v.1));
+
     let not = char('!')
         .skip(skip_spaces())
         .with(parser(expr))
@@ -462,21 +1248,58 @@ fn factor<'a, I>() -> impl Parser<Input = I, Output = ExprType<'a>>
         .with(parser(expr))
         .map(|v| Expr::Neg(Box::new(v)));
 
-    (
+    let list = char('[')
+        .skip(skip_spaces())
+        .with(sep_end_by(parser(expr).skip(skip_spaces()), char(',')))
+        .skip(skip_spaces())
+        .skip(char(']'))
+        .map(Expr::List);
+
+    let index = (
+        position(),
+        char('[')
+            .skip(skip_spaces())
+            .with(parser(expr))
+            .skip(skip_spaces())
+            .skip(char(']')),
+        position(),
+    );
+
+    let primary = (
         position(),
         choice((
             attempt(float_to_int),
             attempt(int_to_float),
             attempt(brackets.map(|v| v.expr)),
+            attempt(parent_property),
+            attempt(ancestor_property),
             attempt(call),
             attempt(value().map(|v| Expr::Value(v.value))),
             attempt(not),
             attempt(neg),
-        ))
-    ).map(|v| ExprType {
-        position: SourcePosition::into(v.0),
-        expr: v.1,
-    })
+            attempt(list),
+        )),
+        position(),
+    ).map(|v| {
+        // Unary operators parse a whole expression which
+        // includes any trailing whitespace
+        let end = match v.1 {
+            Expr::Neg(ref e) | Expr::Not(ref e) => e.span.end,
+            _ => SourcePosition::into(v.2),
+        };
+        ExprType {
+            position: SourcePosition::into(v.0),
+            span: Span { start: SourcePosition::into(v.0), end },
+            expr: v.1,
+        }
+    });
+
+    (primary, many::<Vec<_>, _>(index))
+        .map(|(base, indices)| indices.into_iter().fold(base, |base, (start, index, end)| ExprType {
+            position: SourcePosition::into(start),
+            span: Span { start: base.span.start, end: SourcePosition::into(end) },
+            expr: Expr::Index(Box::new(base), Box::new(index)),
+        }))
 }
 
 fn properties<'a, I>() -> impl Parser<Input = I, Output = FnvHashMap<Ident<'a>, ValueType<'a>>>
@@ -511,22 +1334,28 @@ fn value<'a, I>() -> impl Parser<Input = I, Output = ValueType<'a>>
     let boolean = parse_bool().map(|v| Value::Boolean(v));
     let float = parse_float().map(|v| Value::Float(v));
     let integer = parse_integer().map(|v| Value::Integer(v));
+    let color = parse_color().map(Value::Color);
 
-    let string = parse_string().map(|v| Value::String(v));
+    let string = parse_string().map(Value::String);
 
+    let tagged = (ident(), parse_string()).map(|v| Value::Tagged(v.0, v.1));
     let variable = ident().map(|v| Value::Variable(v));
 
     (
         position(),
-        try(boolean)
-            .or(try(float))
-            .or(try(integer))
-            .or(try(variable))
-            .or(string),
+        attempt(boolean)
+            .or(attempt(float))
+            .or(attempt(integer))
+            .or(string)
+            .or(attempt(tagged))
+            .or(attempt(variable))
+            .or(color),
+        position(),
     ).map(|v| {
             ValueType {
                 value: v.1,
                 position: SourcePosition::into(v.0),
+                span: Span::new(v.0, v.2),
             }
         })
 }
@@ -568,4 +1397,398 @@ panel > @text {
             panic!("^^");
         }
     }
+
+    #[test]
+    fn test_selector_list() {
+        let source = r##"
+button, toggle(on=true), tab > @text {
+    color = "#000000",
+}
+        "##;
+        let doc = Document::parse(source).unwrap();
+        assert_eq!(doc.rules.len(), 1);
+        let rule = &doc.rules[0];
+        assert_eq!(rule.selectors.len(), 3);
+        assert_eq!(rule.selectors[0].matchers.len(), 1);
+        assert_eq!(rule.selectors[1].matchers[0].1.len(), 1);
+        assert_eq!(rule.selectors[2].matchers.len(), 2);
+        assert_eq!(rule.styles.len(), 1);
+    }
+
+    #[test]
+    fn test_priority() {
+        let source = r##"
+@priority(5) button {
+    color = "#000000",
+}
+@priority(-2) @text {
+    color = "#000000",
+}
+toggle {
+    color = "#000000",
+}
+        "##;
+        let doc = Document::parse(source).unwrap();
+        let priorities: Vec<_> = doc.rules.iter().map(|v| v.priority).collect();
+        assert_eq!(priorities, vec![5, -2, 0]);
+    }
+
+    #[test]
+    fn test_nested() {
+        let source = r##"
+alert, dialog(open=true) {
+    width = 5,
+    // Comment
+    title {
+        height = 4,
+        @priority(2) @text {
+            color = "#000000",
+        }
+    }
+    @priority(1) content {
+        @text {
+            color = "#000000",
+        }
+    }
+}
+        "##;
+        let doc = Document::parse(source).unwrap();
+        // The nesting is kept in the document
+        assert_eq!(doc.rules.len(), 1);
+        let nested: Vec<_> = doc.rules[0].nested.iter()
+            .map(|v| (v.priority, v.selectors[0].to_string(), v.nested.len()))
+            .collect();
+        assert_eq!(nested, vec![(0, "title".to_owned(), 1), (1, "content".to_owned(), 1)]);
+        assert_eq!(doc.rules[0].nested[0].nested[0].priority, 2);
+
+        let mut flat = Vec::new();
+        for rule in doc.rules {
+            rule.flatten(&mut flat);
+        }
+        assert!(flat.iter().all(|v| v.nested.is_empty()));
+        let rules: Vec<_> = flat.iter()
+            .map(|v| (
+                v.priority,
+                v.selectors.iter()
+                    .map(|s| s.matchers.iter()
+                        .map(|m| match m.0 {
+                            Matcher::Text => "@text",
+                            Matcher::Element(ref e) => &*e.name.name,
+                        })
+                        .collect::<Vec<_>>()
+                        .join(" > "))
+                    .collect::<Vec<_>>(),
+            ))
+            .collect();
+        assert_eq!(rules, vec![
+            (0, vec!["alert".to_owned(), "dialog".to_owned()]),
+            (0, vec!["alert > title".to_owned(), "dialog > title".to_owned()]),
+            (2, vec!["alert > title > @text".to_owned(), "dialog > title > @text".to_owned()]),
+            (1, vec!["alert > content > @text".to_owned(), "dialog > content > @text".to_owned()]),
+        ]);
+        assert_eq!(flat[0].styles.len(), 1);
+        assert_eq!(flat[1].selectors[1].matchers[0].1.len(), 1);
+    }
+
+    #[test]
+    fn test_mixin() {
+        let source = r##"
+@import "common.style"
+// Base
+@mixin base {
+    width = 5,
+    @include other
+}
+@mixin other {
+    height = 5,
+}
+panel {
+    @include base,
+    @include other,
+    width = 6,
+}
+        "##;
+        let doc = Document::parse(source).unwrap();
+        assert_eq!(doc.mixins.len(), 2);
+        assert_eq!(doc.mixins[0].name.name, "base");
+        assert_eq!(doc.mixins[0].includes.len(), 1);
+        assert_eq!(doc.imports.len(), 1);
+        assert_eq!(doc.imports[0].path, "common.style");
+        assert_eq!(doc.rules.len(), 1);
+        let includes: Vec<_> = doc.rules[0].includes.iter().map(|v| &*v.name).collect();
+        assert_eq!(includes, vec!["base", "other"]);
+
+        assert!(Document::parse(r##"
+@mixin base {
+    panel {
+        width = 5,
+    }
+}
+        "##).is_err());
+    }
+
+    #[test]
+    fn test_ancestor_property() {
+        let source = r##"
+panel {
+    width = parent.width * 2,
+    height = ancestor(dialog).height,
+    parent_test = parent_width,
+}
+        "##;
+        let doc = Document::parse(source).unwrap();
+        let styles = &doc.rules[0].styles;
+        let width = styles.iter().find(|v| v.0.name == "width").unwrap();
+        match width.1.expr {
+            Expr::Mul(ref l, _) => match l.expr {
+                Expr::ParentProperty(ref n) => assert_eq!(n.name, "width"),
+                ref e => panic!("Unexpected expr: {:?}", e),
+            },
+            ref e => panic!("Unexpected expr: {:?}", e),
+        }
+        let height = styles.iter().find(|v| v.0.name == "height").unwrap();
+        match height.1.expr {
+            Expr::AncestorProperty(ref e, ref n) => {
+                assert_eq!(e.name, "dialog");
+                assert_eq!(n.name, "height");
+            },
+            ref e => panic!("Unexpected expr: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        let source = r##"
+@import "common.style"
+// Layout helpers
+@mixin base { width = 5, @include other }
+@import "other.style"
+
+// Panels
+@priority(2) panel(b=1.0,a="x\"y") > @text, dialog{
+    // The width
+    width = ((1 + 2) * 3) - (4 - 5) - -6,
+    height=-(a+b)*2,
+    test = !(a && b) == (c || d ^ e),
+    call = do_thing(5,3, 4*(7%2)) / pi() + float(int(x) + 1),
+    link = parent.width + ancestor(dialog).height,
+    neg = (-a) * 2 + b / (!c),
+    title {
+        size = 1 - (2 + 3),
+    }
+    @include base,
+}
+// Trailing
+        "##;
+        let doc = Document::parse(source).unwrap();
+        let formatted = doc.to_string();
+        assert_eq!(formatted, r##"@import "common.style"
+
+// Layout helpers
+@mixin base {
+    width = 5,
+    @include other,
+}
+
+@import "other.style"
+
+// Panels
+@priority(2) panel(a="x\"y", b=1.0) > @text, dialog {
+    // The width
+    width = (1 + 2) * 3 - (4 - 5) - -6,
+    height = -((a + b) * 2),
+    test = !(a && b == c || d ^ e),
+    call = do_thing(5, 3, 4 * (7 % 2)) / pi() + float(int(x) + 1),
+    link = parent.width + ancestor(dialog).height,
+    neg = (-a) * 2 + b / (!c),
+    title {
+        size = 1 - (2 + 3),
+    }
+    @include base,
+}
+
+// Trailing
+"##);
+    }
+
+    #[test]
+    fn test_format_idempotent() {
+        let sources = [
+            r##"
+// Comment
+root > panel > image(width=width, height=height) {
+    width = width,
+    test_expr2 = -5 + -3,
+    test_expr4 = -3--4,
+    p_test = 5 * (1 + 2) - 3/5,
+    hard_test = -banana() / -(5--4),
+    cmp = a < b == (c >= d),
+    str = "// not a comment",
+}
+            "##,
+            r##"
+alert, dialog(open=true) {
+    width = 5,
+    // Comment
+    title {
+        height = 4,
+        @priority(2) @text {
+            color = "#000000",
+        }
+    }
+}
+            "##,
+            r##"
+@mixin base {
+    // Nested comment
+    width = 1.0 / 3.0,
+}
+            "##,
+        ];
+        for source in &sources {
+            let once = Document::parse(source).unwrap().to_string();
+            let twice = Document::parse(&once).unwrap().to_string();
+            assert_eq!(once, twice);
+        }
+    }
+
+    #[test]
+    fn test_spans() {
+        let source = r##"// Header
+panel > @text {
+    width = -a + 2 ,
+    // Inside
+    height = parent.width,
+}
+"##;
+        let doc = Document::parse(source).unwrap();
+        let span = |s: Span| (s.start.line_number, s.start.column, s.end.line_number, s.end.column);
+        let rule = &doc.rules[0];
+        assert_eq!(span(rule.span), (2, 1, 6, 2));
+        match rule.selectors[0].matchers[0].0 {
+            Matcher::Element(ref e) => assert_eq!(span(e.span), (2, 1, 2, 6)),
+            Matcher::Text => panic!("Expected element"),
+        }
+        let (key, width) = rule.styles.iter().find(|v| v.0.name == "width").unwrap();
+        assert_eq!(span(key.span), (3, 5, 3, 10));
+        // Neg contains the whole addition
+        assert_eq!(span(width.span), (3, 13, 3, 19));
+        let (_, height) = rule.styles.iter().find(|v| v.0.name == "height").unwrap();
+        assert_eq!(span(height.span), (5, 14, 5, 26));
+
+        let comments: Vec<_> = doc.comments.iter().map(|v| (&*v.text, span(v.span))).collect();
+        assert_eq!(comments, vec![
+            (" Header", (1, 1, 1, 10)),
+            (" Inside", (4, 5, 4, 14)),
+        ]);
+    }
+
+    #[test]
+    fn test_recovering() {
+        let source = r##"
+panel {
+    width = 5 +,
+}
+@import common.style
+button {
+    color = "}",
+    height = 3,
+}
+label > {
+    width = 1,
+}
+@mixin base {
+    width = 2,
+}
+toggle {
+    width = (1,
+"##;
+        let (doc, errors) = Document::parse_recovering(source);
+        let rules: Vec<_> = doc.rules.iter()
+            .map(|v| v.selectors[0].to_string())
+            .collect();
+        assert_eq!(rules, vec!["button"]);
+        assert_eq!(doc.mixins.len(), 1);
+        let lines: Vec<_> = errors.iter().map(|v| v.position.line_number).collect();
+        assert_eq!(lines, vec![3, 5, 10, 17]);
+
+        let mut out: Vec<u8> = Vec::new();
+        errors[0].format(&mut out, source.lines()).unwrap();
+        assert!(String::from_utf8_lossy(&out).contains("3 |     width = 5 +,"));
+
+        // Valid documents parse the same in both modes
+        let (doc, errors) = Document::parse_recovering("panel { width = 5, }");
+        assert!(errors.is_empty());
+        assert_eq!(doc.rules.len(), 1);
+    }
+
+    #[test]
+    fn test_literals() {
+        let doc = Document::parse(r#"
+panel {
+    a = 1-2,
+    b = .5e2 * -0x10,
+    c = #FF000080,
+    d = "\u{2764}",
+}
+        "#).unwrap();
+        assert_eq!(doc.to_string(), r#"panel {
+    a = 1 - 2,
+    b = 50.0 * -16,
+    c = #ff000080,
+    d = "\u{2764}",
+}
+"#);
+    }
+
+    #[test]
+    fn test_raw_strings() {
+        let doc = Document::parse(r##"
+/* Buttons */
+button {
+    on_click = r#"print("clicked")"#,
+    /* The label */
+    label = r"\o/",
+}
+        "##).unwrap();
+        assert_eq!(doc.to_string(), r#"/* Buttons */
+button {
+    on_click = "print(\"clicked\")",
+    /* The label */
+    label = "\\o/",
+}
+"#);
+    }
+
+    #[test]
+    fn test_lists() {
+        let doc = Document::parse(r#"
+panel {
+    a = [1, 2 + 3][0],
+    b = len(["x", []]),
+    c = ([1] + [2])[1],
+}
+        "#).unwrap();
+        assert_eq!(doc.to_string(), r#"panel {
+    a = [1, 2 + 3][0],
+    b = len(["x", []]),
+    c = ([1] + [2])[1],
+}
+"#);
+    }
+
+    #[test]
+    fn test_tagged_literals() {
+        let doc = Document::parse(r#"
+button(icon=img"ok") {
+    background = img"ui/button",
+    size = len(list"a, b"),
+}
+        "#).unwrap();
+        assert_eq!(doc.to_string(), r#"button(icon=img"ok") {
+    background = img"ui/button",
+    size = len(list"a, b"),
+}
+"#);
+    }
 }